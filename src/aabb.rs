@@ -0,0 +1,81 @@
+use std::ops::Range;
+
+use crate::vector::Vec3;
+use crate::ray::Ray;
+use crate::rt_util;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3
+}
+
+impl Aabb {
+    pub fn new(a: Vec3, b: Vec3) -> Aabb {
+        Aabb {
+            min: a.min(&b),
+            max: a.max(&b)
+        }
+    }
+    
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::new(rt_util::INFINITY, rt_util::INFINITY, rt_util::INFINITY),
+            max: Vec3::new(-rt_util::INFINITY, -rt_util::INFINITY, -rt_util::INFINITY)
+        }
+    }
+    
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb {
+            min: a.min.min(&b.min),
+            max: a.max.max(&b.max)
+        }
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+    
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+    
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        
+        let extent = self.max - self.min;
+        
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+    
+    pub fn hit(&self, ray: &Ray, t_range: Range<f64>) -> bool {
+        self.clip(ray, t_range).is_some()
+    }
+    
+    // Part of the ray range inside the box, None when the ray misses it
+    pub fn clip(&self, ray: &Ray, t_range: Range<f64>) -> Option<(f64, f64)> {
+        let mut t_min = t_range.start;
+        let mut t_max = t_range.end;
+        
+        for axis in 0..3 {
+            let inv_dir = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_dir;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_dir;
+            
+            if inv_dir < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            
+            if t_max <= t_min {
+                return None;
+            }
+        }
+        
+        Some((t_min, t_max))
+    }
+}
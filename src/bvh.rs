@@ -0,0 +1,180 @@
+use std::ops::Range;
+
+use crate::aabb::Aabb;
+use crate::primitive::*;
+use crate::ray::Ray;
+
+// Relative cost of an extra box test against a primitive test, used by the SAH split search
+const TRAVERSAL_COST: f64 = 0.125;
+
+pub struct BvhNode {
+    left: Box<dyn Hittable>,
+    right: Option<Box<dyn Hittable>>,
+    bbox: Aabb
+}
+
+impl BvhNode {
+    pub fn new(list: HittableList) -> Self {
+        BvhNode::from_objects(list.into_objects())
+    }
+    
+    fn from_objects(mut objects: Vec<Box<dyn Hittable>>) -> Self {
+        let bbox = objects.iter().fold(Aabb::empty(), |bbox, object| Aabb::surrounding(&bbox, &object.bounding_box()));
+        
+        match objects.len() {
+            0 => BvhNode {left: Box::new(HittableList::new()), right: None, bbox},
+            1 => BvhNode {left: objects.remove(0), right: None, bbox},
+            2 => {
+                let right = objects.pop();
+                let left = objects.remove(0);
+                
+                BvhNode {left, right, bbox}
+            },
+            _ => {
+                let (axis, split) = BvhNode::sah_split(&mut objects, &bbox);
+                
+                objects.sort_by(|a, b| BvhNode::centroid_on_axis(a.as_ref(), axis).total_cmp(&BvhNode::centroid_on_axis(b.as_ref(), axis)));
+                let right_objects = objects.split_off(split);
+                
+                BvhNode {
+                    left: BvhNode::subtree(objects),
+                    right: Some(BvhNode::subtree(right_objects)),
+                    bbox
+                }
+            }
+        }
+    }
+    
+    fn subtree(mut objects: Vec<Box<dyn Hittable>>) -> Box<dyn Hittable> {
+        if objects.len() == 1 {
+            return objects.remove(0);
+        }
+        
+        Box::new(BvhNode::from_objects(objects))
+    }
+    
+    fn centroid_on_axis(object: &dyn Hittable, axis: usize) -> f64 {
+        object.bounding_box().centroid()[axis]
+    }
+    
+    // Sweeps every axis over the centroid-sorted objects and returns the axis and split index
+    // with the lowest surface area heuristic cost
+    fn sah_split(objects: &mut [Box<dyn Hittable>], bbox: &Aabb) -> (usize, usize) {
+        let count = objects.len();
+        let parent_area = bbox.surface_area().max(f64::EPSILON);
+        
+        let mut best_axis = 0;
+        let mut best_split = count / 2;
+        let mut best_cost = f64::INFINITY;
+        
+        let mut right_areas = vec![0.0; count];
+        
+        for axis in 0..3 {
+            objects.sort_by(|a, b| BvhNode::centroid_on_axis(a.as_ref(), axis).total_cmp(&BvhNode::centroid_on_axis(b.as_ref(), axis)));
+            
+            let mut right_bbox = Aabb::empty();
+            for i in (1..count).rev() {
+                right_bbox = Aabb::surrounding(&right_bbox, &objects[i].bounding_box());
+                right_areas[i] = right_bbox.surface_area();
+            }
+            
+            let mut left_bbox = Aabb::empty();
+            for split in 1..count {
+                left_bbox = Aabb::surrounding(&left_bbox, &objects[split - 1].bounding_box());
+                
+                let left_cost = left_bbox.surface_area() * split as f64;
+                let right_cost = right_areas[split] * (count - split) as f64;
+                let cost = TRAVERSAL_COST + (left_cost + right_cost) / parent_area;
+                
+                if cost < best_cost {
+                    best_cost = cost;
+                    best_axis = axis;
+                    best_split = split;
+                }
+            }
+        }
+        
+        (best_axis, best_split)
+    }
+}
+
+impl BvhNode {
+    // Shared by hit and hit_surface, which only differ in how the children are tested
+    fn closest_hit(&self, ray: &Ray, t_range: Range<f64>, child_hit: impl Fn(&dyn Hittable, &Ray, Range<f64>) -> RayHitResult) -> RayHitResult {
+        if !self.bbox.hit(ray, t_range.clone()) {
+            return RayHitResult::default();
+        }
+        
+        let hit_left = child_hit(self.left.as_ref(), ray, t_range.clone());
+        
+        let right = match &self.right {
+            Some(right) => right,
+            None => return hit_left
+        };
+        
+        let closest_t = if hit_left.is_hit { hit_left.data.ray_t } else { t_range.end };
+        let hit_right = child_hit(right.as_ref(), ray, t_range.start..closest_t);
+        
+        if hit_right.is_hit {
+            return hit_right;
+        }
+        
+        hit_left
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        self.closest_hit(ray, t_range, |child, ray, t_range| child.hit(ray, t_range))
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+    
+    fn hit_surface(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        self.closest_hit(ray, t_range, |child, ray, t_range| child.hit_surface(ray, t_range))
+    }
+    
+    fn transmittance(&self, ray: &Ray, t_range: Range<f64>) -> f64 {
+        if !self.bbox.hit(ray, t_range.clone()) {
+            return 1.0;
+        }
+        
+        let right = self.right.as_ref().map_or(1.0, |right| right.transmittance(ray, t_range.clone()));
+        
+        self.left.transmittance(ray, t_range) * right
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt_util;
+    use crate::vector::Vec3;
+    
+    #[test]
+    fn hits_match_linear_scan() {
+        // The scene generator reseeds itself, so both copies hold the same spheres
+        let list = crate::random_spheres_scene(0).world;
+        let bvh = BvhNode::new(crate::random_spheres_scene(0).world);
+        
+        rt_util::seed_rng(1);
+        for _ in 0..20000 {
+            let origin = Vec3::new(rt_util::random_range(-15.0..15.0), rt_util::random_range(0.05..4.0), rt_util::random_range(-15.0..15.0));
+            let ray = Ray::new(origin, rt_util::random_on_unit_sphere(), 0.0);
+            
+            let expected = list.hit(&ray, 0.001..rt_util::INFINITY);
+            let actual = bvh.hit(&ray, 0.001..rt_util::INFINITY);
+            
+            assert_eq!(expected.is_hit, actual.is_hit);
+            if expected.is_hit {
+                // Each build makes its own materials, the same object has the same point, normal and material
+                assert_eq!(expected.data.ray_t, actual.data.ray_t);
+                assert_eq!(expected.data.point, actual.data.point);
+                assert_eq!(expected.data.normal, actual.data.normal);
+                assert_eq!(format!("{:?}", expected.data.material), format!("{:?}", actual.data.material));
+            }
+        }
+    }
+}
@@ -1,14 +1,179 @@
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
 use indicatif::{ProgressBar, ProgressStyle};
-use rand::prelude::*;
 
+use crate::aabb::Aabb;
 use crate::cross;
 use crate::vector::Vec3;
 use crate::ray::Ray;
 use crate::color::*;
+use crate::environment::EnvironmentMap;
+use crate::sky::SunSky;
+use crate::image::Image;
+use crate::light::DeltaLight;
+use crate::material::Material;
+use crate::medium::{ConstantMedium, HenyeyGreenstein};
 use crate::primitive::*;
 use crate::rt_util;
+use crate::spectrum::Wavelengths;
+
+const TILE_SIZE: u32 = 32;
+// 16384 x 16384, the framebuffer alone is several gigabytes at this size
+const MAX_FRAME_PIXELS: u64 = 1 << 28;
+
+#[derive(Clone, Debug)]
+pub enum Background {
+    Black,
+    Solid(Color01),
+    // Vertical blend from the horizon color looking down to the zenith color looking up
+    Gradient {
+        bottom: Color01,
+        top: Color01
+    },
+    Environment(Arc<EnvironmentMap>),
+    Sky(Arc<SunSky>)
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Color01 {
+        match self {
+            Background::Black => Color01::default(),
+            Background::Solid(color) => *color,
+            Background::Gradient {bottom, top} => {
+                let ray_dir_norm = ray.direction.normalized();
+                let interp = (ray_dir_norm.y + 1.0) * 0.5;
+                
+                (1.0 - interp) * *bottom + interp * *top
+            },
+            Background::Environment(environment) => environment.radiance(&ray.direction),
+            Background::Sky(sky) => sky.radiance(&ray.direction)
+        }
+    }
+    
+    // Environment maps and skies are sampled as lights, the other backgrounds are found by BSDF sampling
+    fn is_sampled(&self) -> bool {
+        matches!(self, Background::Environment(_) | Background::Sky(_))
+    }
+    
+    fn sample_direction(&self) -> Vec3 {
+        match self {
+            Background::Environment(environment) => environment.sample_direction(),
+            Background::Sky(sky) => sky.sample_direction(),
+            _ => Vec3::new(0.0, 1.0, 0.0)
+        }
+    }
+    
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        match self {
+            Background::Environment(environment) => environment.pdf(direction),
+            Background::Sky(sky) => sky.pdf(direction),
+            _ => 0.0
+        }
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Gradient {
+            bottom: Color01 {r: 1.0, g: 1.0, b: 1.0},
+            top: Color01 {r: 0.5, g: 0.7, b: 1.0}
+        }
+    }
+}
+
+// How light reaching non-specular surfaces is found. Bsdf and Light converge to the same image and
+// are kept to check Mis against
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightSampling {
+    Bsdf,
+    Light,
+    Mis
+}
+
+impl FromStr for LightSampling {
+    type Err = String;
+    
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "bsdf" => Ok(LightSampling::Bsdf),
+            "light" => Ok(LightSampling::Light),
+            "mis" => Ok(LightSampling::Mis),
+            _ => Err(format!("unknown light sampling '{}', expected bsdf, light or mis", name))
+        }
+    }
+}
+
+// Homogeneous fog filling a sphere of `radius` around the camera. Rays leaving it run free, so the
+// background still shows through thin enough fog
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Atmosphere {
+    pub density: f64,
+    pub albedo: Color01,
+    // Henyey-Greenstein asymmetry, positive values scatter forward
+    pub anisotropy: f64,
+    pub radius: f64
+}
+
+impl Atmosphere {
+    fn medium(&self, center: Vec3) -> ConstantMedium {
+        let phase = Arc::new(HenyeyGreenstein::new(self.albedo, self.anisotropy));
+        let boundary = Sphere {center, radius: self.radius, material: phase.clone()};
+        
+        ConstantMedium::new(Arc::new(boundary), self.density, phase)
+    }
+}
+
+// What every tile of one render shares, the finished tiles are copied into the framebuffer
+struct RenderState<'a> {
+    world: &'a dyn Hittable,
+    lights: &'a HittableList,
+    delta_lights: &'a [DeltaLight],
+    framebuffer: &'a Mutex<Image>,
+    progress_bar: &'a ProgressBar
+}
+
+// The world seen through the camera's atmosphere, a scattering event in the fog hides any surface
+// behind it
+struct Surroundings<'a> {
+    world: &'a HittableList,
+    atmosphere: Option<ConstantMedium>
+}
+
+impl Hittable for Surroundings<'_> {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        let hit = self.world.hit(ray, t_range.clone());
+        let atmosphere = match &self.atmosphere {
+            Some(atmosphere) => atmosphere,
+            None => return hit
+        };
+        
+        let closest_t = if hit.is_hit { hit.data.ray_t } else { t_range.end };
+        let scattering = atmosphere.hit(ray, t_range.start..closest_t);
+        
+        if scattering.is_hit { scattering } else { hit }
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        self.world.bounding_box()
+    }
+    
+    fn hit_surface(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        self.world.hit_surface(ray, t_range)
+    }
+    
+    fn transmittance(&self, ray: &Ray, t_range: Range<f64>) -> f64 {
+        let atmosphere = self.atmosphere.as_ref().map_or(1.0, |atmosphere| atmosphere.transmittance(ray, t_range.clone()));
+        
+        self.world.transmittance(ray, t_range) * atmosphere
+    }
+}
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct Camera {
     pub position: Vec3,
     pub look_at: Vec3,
@@ -16,10 +181,24 @@ pub struct Camera {
     pub fov_vertical: f64,
     pub defocus_angle: f64,
     pub focus_distance: f64,
+    // Each camera ray picks a random time in this interval, moving objects blur over it
+    pub shutter_open: f64,
+    pub shutter_close: f64,
     pub aspect_ratio: f64,
     pub frame_width: u32,
     pub samples_per_pixel: u16,
     pub ray_bounces_max: u16,
+    // Paths longer than this many bounces are randomly terminated based on the energy they carry
+    pub roulette_depth: u16,
+    pub seed: u64,
+    pub background: Background,
+    pub light_sampling: LightSampling,
+    pub atmosphere: Option<Atmosphere>,
+    // Follows each path at a few wavelengths instead of RGB, dispersive glass splits light into colors
+    pub spectral: bool,
+    // Zero uses every available core
+    pub thread_count: usize,
+    pub show_progress: bool,
     
     pixel_zero: Vec3,
     pixel_delta_u: Vec3,
@@ -64,37 +243,132 @@ impl Camera {
         self.defocus_disk_v = self.v * defocus_radius;
     }
     
-    pub fn render(&mut self, world: &HittableList) {
-        self.initialize();
+    pub fn validate(&self) -> Result<(), String> {
+        if self.frame_width == 0 {
+            return Err(String::from("frame width must be at least 1 pixel"));
+        }
+        
+        if !self.aspect_ratio.is_finite() || self.aspect_ratio <= 0.0 {
+            return Err(format!("aspect ratio must be a positive number, got {}", self.aspect_ratio));
+        }
+        
+        let frame_height = (self.frame_width as f64 / self.aspect_ratio) as u32;
+        if frame_height == 0 {
+            return Err(format!("aspect ratio {} with a frame width of {} gives a frame height of 0 pixels", self.aspect_ratio, self.frame_width));
+        }
         
-        rt_util::print_image_header(self.frame_width, self.frame_height);
+        match self.frame_width.checked_mul(frame_height) {
+            Some(frame_res) if frame_res as u64 <= MAX_FRAME_PIXELS => {},
+            _ => return Err(format!("a {} x {} frame is larger than the limit of {} pixels", self.frame_width, frame_height, MAX_FRAME_PIXELS))
+        }
+        
+        if self.shutter_close < self.shutter_open {
+            return Err(format!("shutter close ({}) must not come before shutter open ({})", self.shutter_close, self.shutter_open));
+        }
+        
+        if self.samples_per_pixel == 0 {
+            return Err(String::from("samples per pixel must be at least 1"));
+        }
+        
+        if self.ray_bounces_max == 0 {
+            return Err(String::from("ray bounces max must be at least 1"));
+        }
+        
+        Ok(())
+    }
+    
+    // Lights are the emissive objects of the world that get sampled directly, they must also be in the world.
+    // Delta lights have no geometry and are only reached through shadow rays
+    pub fn render(&mut self, world: &HittableList, lights: &HittableList, delta_lights: &[DeltaLight]) -> Image {
+        self.initialize();
         
-        let progress_bar = ProgressBar::new(self.frame_res as u64);
+        let progress_bar = if self.show_progress {
+            ProgressBar::new(self.frame_res as u64)
+        }
+        else {
+            ProgressBar::hidden()
+        };
         progress_bar.set_style(ProgressStyle::with_template("[{elapsed_precise}] |{bar:40.cyan/blue}| {percent}%")
             .unwrap()
             .progress_chars("=> "));
         
-        for i in 0..self.frame_height {
-            for j in 0..self.frame_width {
+        let surroundings = Surroundings {
+            world,
+            atmosphere: self.atmosphere.map(|atmosphere| atmosphere.medium(self.position))
+        };
+        
+        let framebuffer = Mutex::new(Image::new(self.frame_width, self.frame_height));
+        let state = RenderState {world: &surroundings, lights, delta_lights, framebuffer: &framebuffer, progress_bar: &progress_bar};
+        
+        let tiles_x = self.frame_width.div_ceil(TILE_SIZE);
+        let tiles_y = self.frame_height.div_ceil(TILE_SIZE);
+        let tile_count = (tiles_x * tiles_y) as usize;
+        let next_tile = AtomicUsize::new(0);
+        
+        let thread_count = if self.thread_count > 0 {
+            self.thread_count
+        }
+        else {
+            thread::available_parallelism().map_or(1, |count| count.get())
+        };
+        
+        thread::scope(|scope| {
+            for _ in 0..thread_count {
+                scope.spawn(|| {
+                    loop {
+                        let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+                        if tile >= tile_count {
+                            break;
+                        }
+                        
+                        let tile_x = (tile as u32 % tiles_x) * TILE_SIZE;
+                        let tile_y = (tile as u32 / tiles_x) * TILE_SIZE;
+                        
+                        self.render_tile(&state, tile_x, tile_y);
+                    }
+                });
+            }
+        });
+        
+        progress_bar.finish();
+        
+        framebuffer.into_inner().unwrap()
+    }
+    
+    fn render_tile(&self, state: &RenderState, tile_x: u32, tile_y: u32) {
+        let tile_w = TILE_SIZE.min(self.frame_width - tile_x);
+        let tile_h = TILE_SIZE.min(self.frame_height - tile_y);
+        
+        let mut tile_colors: Vec<Color01> = Vec::with_capacity((tile_w * tile_h) as usize);
+        
+        for i in tile_y..tile_y + tile_h {
+            for j in tile_x..tile_x + tile_w {
                 let mut color = Color01::default();
                 
-                for _ in 0..self.samples_per_pixel {
+                let pixel_index = (i * self.frame_width + j) as u64;
+                
+                for sample in 0..self.samples_per_pixel {
+                    rt_util::seed_rng(rt_util::hash_seed(self.seed, &[pixel_index, sample as u64]));
+                    
                     let ray: Ray = self.get_ray(i, j);
-                    color += Camera::ray_color(&ray, self.ray_bounces_max, &world) * self.pixel_samples_scale;
+                    color += self.ray_color(ray, state.world, state.lights, state.delta_lights) * self.pixel_samples_scale;
                 }
                 
-                print_color_01(color);
-                
-                progress_bar.inc(1);
+                tile_colors.push(color);
             }
         }
         
-        progress_bar.finish();
+        let mut image = state.framebuffer.lock().unwrap();
+        for (row, tile_row) in tile_colors.chunks(tile_w as usize).enumerate() {
+            let start = ((tile_y + row as u32) * self.frame_width + tile_x) as usize;
+            image.pixels[start..start + tile_w as usize].copy_from_slice(tile_row);
+        }
+        
+        state.progress_bar.inc((tile_w * tile_h) as u64);
     }
     
     fn get_ray(&self, i: u32, j: u32) -> Ray {
-        let mut rng = rand::thread_rng();
-        let offset = Vec3::new(rng.gen::<f64>() - 0.5, rng.gen::<f64>() - 0.5, 0.0);
+        let offset = Vec3::new(rt_util::random() - 0.5, rt_util::random() - 0.5, 0.0);
         let pixel_sample = self.pixel_zero + (self.pixel_delta_u * (j as f64 + offset.x)) + (self.pixel_delta_v * (i as f64 + offset.y));
         
         let ray_origin = if self.defocus_angle <= 0.0 {
@@ -105,8 +379,20 @@ impl Camera {
         };
         
         let ray_direction = pixel_sample - ray_origin;
+        // No random draw for an instant shutter, scenes without motion render as they did before
+        let ray_time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + rt_util::random() * (self.shutter_close - self.shutter_open)
+        }
+        else {
+            self.shutter_open
+        };
+        
+        let mut ray = Ray::new(ray_origin, ray_direction, ray_time);
+        if self.spectral {
+            ray.wavelength = Some(Wavelengths::sample_hero());
+        }
         
-        Ray::new(ray_origin, ray_direction)
+        ray
     }
     
     fn defocus_disk_sample(&self) -> Vec3 {
@@ -115,33 +401,209 @@ impl Camera {
         self.position + point.x * self.defocus_disk_u + point.y * self.defocus_disk_v
     } 
     
-    fn ray_color(ray: &Ray, depth: u16, world: &HittableList) -> Color01 {
-        if depth <= 0 {
-            return Color01::default();
-        }
+    // Follows one path bounce by bounce, throughput is the fraction of light at the current hit
+    // that makes it back to the camera
+    fn ray_color(&self, camera_ray: Ray, world: &dyn Hittable, lights: &HittableList, delta_lights: &[DeltaLight]) -> Color01 {
+        let mut ray = camera_ray;
+        let mut color = Color01::default();
+        let mut throughput = Color01::new(1.0, 1.0, 1.0);
+        // MIS weight of the bounce that produced the current ray, hitting a light that was also sampled
+        // directly must only add the BSDF strategy's share of its emission
+        let mut emission_weight = 1.0;
+        // Set once a dispersive bounce left only the hero wavelength on a spectral path
+        let mut hero_only = false;
         
-        let hit_result = world.hit(ray, 0.001..rt_util::INFINITY);
-        if hit_result.is_hit {
-            let bounce = hit_result.data.material.scatter(ray, &hit_result);
+        for depth in 0..self.ray_bounces_max {
+            let hit_result = world.hit(&ray, 0.001..rt_util::INFINITY);
+            if !hit_result.is_hit {
+                let weight = if self.background.is_sampled() { emission_weight } else { 1.0 };
+                color += throughput * path_values(self.background.color(&ray), &ray) * weight;
+                break;
+            }
+            
+            let material = match hit_result.data.material.as_deref() {
+                Some(material) => material,
+                None => break
+            };
+            
+            // Only lights were sampled directly, other emitters like glowing volumes count fully
+            let record = material.scatter(&ray, &hit_result);
+            color += throughput * path_values(record.emission, &ray) * if material.is_emissive() { emission_weight } else { 1.0 };
+            
+            let scattered = match record.scattered {
+                Some(scattered) => scattered,
+                None => break
+            };
             
-            if bounce.2 {
-                return Camera::ray_color(&bounce.0, depth - 1, world) * bounce.1;
+            if !record.is_specular && !delta_lights.is_empty() {
+                color += throughput * self.sample_delta_lights(&ray, &hit_result, material, world, delta_lights);
+            }
+            
+            let has_lights = !lights.is_empty() || self.background.is_sampled();
+            emission_weight = if record.is_specular || !has_lights || self.light_sampling == LightSampling::Bsdf {
+                1.0
+            }
+            else {
+                color += throughput * self.sample_direct_light(&ray, &hit_result, material, world, lights);
+                
+                match self.light_sampling {
+                    LightSampling::Light => 0.0,
+                    _ => rt_util::power_heuristic(record.pdf, self.light_pdf(lights, &hit_result.data.point, &scattered.direction, scattered.time))
+                }
+            };
+            
+            throughput = throughput * bsdf_values(material, record.attenuation, &ray);
+            if record.dispersive && !hero_only {
+                throughput = Wavelengths::hero_only(throughput);
+                hero_only = true;
+            }
+            
+            ray = Ray {wavelength: ray.wavelength, ..scattered};
+            
+            // Russian roulette, surviving paths are scaled up by the survival probability so the
+            // estimate stays unbiased. The cap stops lossless paths like glass from never ending
+            if depth + 1 >= self.roulette_depth {
+                let survival = throughput.max_component().min(0.95);
+                if rt_util::random() >= survival {
+                    break;
+                }
+                
+                throughput = throughput * (1.0 / survival);
             }
+        }
+        
+        match camera_ray.wavelength {
+            Some(hero) => Wavelengths::from_hero(hero).to_rgb(color),
+            None => color
+        }
+    }
+    
+    // Chance of next event estimation sampling the background instead of the light objects
+    fn background_chance(&self, lights: &HittableList) -> f64 {
+        if !self.background.is_sampled() {
+            0.0
+        }
+        else if lights.is_empty() {
+            1.0
+        }
+        else {
+            0.5
+        }
+    }
+    
+    // Solid angle density of next event estimation picking `direction`, a mixture of the light objects
+    // and the background
+    fn light_pdf(&self, lights: &HittableList, origin: &Vec3, direction: &Vec3, time: f64) -> f64 {
+        let chance = self.background_chance(lights);
+        let mut pdf = 0.0;
+        
+        if chance < 1.0 {
+            pdf += (1.0 - chance) * lights.pdf_value(origin, direction, time);
+        }
+        
+        if chance > 0.0 {
+            pdf += chance * self.background.pdf(direction);
+        }
+        
+        pdf
+    }
+    
+    // Next event estimation: picks a point on a light, checks that nothing blocks it and weights its
+    // emission by the BSDF, the cosine and the solid angle density of the light sample
+    fn sample_direct_light(&self, ray: &Ray, hit: &RayHitResult, material: &dyn Material, world: &dyn Hittable, lights: &HittableList) -> Color01 {
+        let origin = hit.data.point;
+        let direction = if self.background.is_sampled() && rt_util::random() < self.background_chance(lights) {
+            self.background.sample_direction()
+        }
+        else {
+            lights.random_direction(&origin, ray.time)
+        };
+        
+        let light_pdf = self.light_pdf(lights, &origin, &direction, ray.time);
+        if light_pdf <= 0.0 {
+            return Color01::default();
+        }
+        
+        let bsdf_cos = material.eval(ray, hit, &direction);
+        if bsdf_cos.r <= 0.0 && bsdf_cos.g <= 0.0 && bsdf_cos.b <= 0.0 {
+            return Color01::default();
+        }
+        
+        // Volumes in between dim the light instead of blocking it at random
+        let shadow_ray = Ray::new(origin, direction, ray.time);
+        let shadow_hit = world.hit_surface(&shadow_ray, 0.001..rt_util::INFINITY);
+        
+        // Shadow rays that escape see the background, if it's one that gets sampled
+        let (light_emission, light_t) = if shadow_hit.is_hit {
+            let emission = match &shadow_hit.data.material {
+                Some(light_material) => light_material.emitted(&shadow_hit),
+                None => Color01::default()
+            };
             
+            (emission, shadow_hit.data.ray_t)
+        }
+        else if self.background.is_sampled() {
+            (self.background.color(&shadow_ray), rt_util::INFINITY)
+        }
+        else {
+            return Color01::default();
+        };
+        
+        let transmittance = world.transmittance(&shadow_ray, 0.001..light_t);
+        if transmittance <= 0.0 {
             return Color01::default();
         }
         
-        let ray_dir_norm = ray.direction.normalized();
-        let interp = (ray_dir_norm.y + 1.0) * 0.5;
-        let color_white = Color01 {r: 1.0, g: 1.0, b: 1.0};
-        let color_blue = Color01 {r: 0.5, g: 0.7, b: 1.0};
+        let light_weight = match self.light_sampling {
+            LightSampling::Light => 1.0,
+            _ => rt_util::power_heuristic(light_pdf, material.scattering_pdf(ray, hit, &direction))
+        };
         
-        let color_01 = (1.0 - interp) * color_white + interp * color_blue;
+        path_values(light_emission, ray) * bsdf_values(material, bsdf_cos, ray) * (transmittance * light_weight / light_pdf)
+    }
+    
+    // BSDF samples never find delta lights, so all of them are sampled at every non-specular bounce
+    // whatever the light sampling mode
+    fn sample_delta_lights(&self, ray: &Ray, hit: &RayHitResult, material: &dyn Material, world: &dyn Hittable, delta_lights: &[DeltaLight]) -> Color01 {
+        let mut color = Color01::default();
         
-        color_01
+        for light in delta_lights {
+            let sample = match light.illuminate(&hit.data.point) {
+                Some(sample) => sample,
+                None => continue
+            };
+            
+            let bsdf_cos = material.eval(ray, hit, &sample.direction);
+            if bsdf_cos.r <= 0.0 && bsdf_cos.g <= 0.0 && bsdf_cos.b <= 0.0 {
+                continue;
+            }
+            
+            let shadow_ray = Ray::new(hit.data.point, sample.direction, ray.time);
+            if world.hit_surface(&shadow_ray, 0.001..sample.distance).is_hit {
+                continue;
+            }
+            
+            color += path_values(sample.radiance, ray) * bsdf_values(material, bsdf_cos, ray) * world.transmittance(&shadow_ray, 0.001..sample.distance);
+        }
+        
+        color
+    }
+}
+
+// Turns an RGB quantity met along the ray's path into path values, its spectrum at the path's wavelengths
+// for spectral renders
+fn path_values(color: Color01, ray: &Ray) -> Color01 {
+    match ray.wavelength {
+        Some(hero) => Wavelengths::from_hero(hero).upsample(color),
+        None => color
     }
 }
 
+// Path values of a material's attenuation or eval() result
+fn bsdf_values(material: &dyn Material, color: Color01, ray: &Ray) -> Color01 {
+    if material.is_spectral() { color } else { path_values(color, ray) }
+}
+
 impl Default for Camera {
     fn default() -> Self {
         let position: Vec3 = Vec3 {x: 0.0, y: 0.0, z: 0.0};
@@ -155,6 +617,8 @@ impl Default for Camera {
         let frustum_h: f64 = (rt_util::deg_to_rad(fov_vertical) * 0.5).tan();
         let defocus_angle: f64 = 0.0;
         let focus_distance: f64 = 10.0;
+        let shutter_open: f64 = 0.0;
+        let shutter_close: f64 = 0.0;
         let view_height: f64 = 2.0 * frustum_h * focus_distance;
         let view_width: f64 = view_height * (frame_width as f64 / frame_height as f64);
         
@@ -171,6 +635,14 @@ impl Default for Camera {
         let samples_per_pixel: u16 = 10;
         let pixel_samples_scale: f64 = 1.0/samples_per_pixel as f64;
         let ray_bounces_max: u16 = 10;
+        let roulette_depth: u16 = 3;
+        let seed: u64 = 0;
+        let background = Background::default();
+        let light_sampling = LightSampling::Mis;
+        let atmosphere = None;
+        let spectral = false;
+        let thread_count: usize = 0;
+        let show_progress = true;
         
         let defocus_radius = focus_distance * rt_util::deg_to_rad(defocus_angle * 0.5).tan();
         let defocus_disk_u = u * defocus_radius;
@@ -183,10 +655,20 @@ impl Default for Camera {
             fov_vertical,
             defocus_angle,
             focus_distance,
+            shutter_open,
+            shutter_close,
             aspect_ratio,
             frame_width,
             samples_per_pixel,
             ray_bounces_max,
+            roulette_depth,
+            seed,
+            background,
+            light_sampling,
+            atmosphere,
+            spectral,
+            thread_count,
+            show_progress,
             pixel_zero,
             pixel_delta_u,
             pixel_delta_v,
@@ -201,3 +683,104 @@ impl Default for Camera {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Dielectric, DiffuseLight, Lambertian};
+    use crate::principled::Principled;
+    use crate::texture;
+    
+    #[test]
+    fn image_does_not_depend_on_thread_count() {
+        let light = Sphere {center: Vec3::new(0.0, 4.0, 0.0), radius: 1.0, material: Arc::new(DiffuseLight::new(Color01::new(1.0, 1.0, 1.0), 4.0))};
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere {center: Vec3::new(0.0, -100.0, 0.0), radius: 100.0, material: Arc::new(Lambertian::new(Color01::new(0.5, 0.5, 0.5)))}));
+        world.add(Box::new(Sphere {center: Vec3::new(0.0, 1.0, 0.0), radius: 1.0, material: Arc::new(Dielectric::new(1.5))}));
+        world.add(Box::new(light.clone()));
+        let mut lights = HittableList::new();
+        lights.add(Box::new(light));
+        
+        let render = |thread_count: usize| {
+            let mut camera = Camera {
+                position: Vec3::new(0.0, 2.0, 6.0),
+                look_at: Vec3::new(0.0, 1.0, 0.0),
+                // Several tiles, some of them cut off at the frame edges
+                frame_width: 80,
+                aspect_ratio: 80.0 / 48.0,
+                samples_per_pixel: 4,
+                ray_bounces_max: 8,
+                seed: 3,
+                thread_count,
+                show_progress: false,
+                ..Camera::default()
+            };
+            
+            camera.render(&world, &lights, &[]).pixels
+        };
+        
+        let single_thread = render(1);
+        for thread_count in [2, 3, 8] {
+            assert!(render(thread_count) == single_thread, "{} threads rendered a different image", thread_count);
+        }
+    }
+    
+    #[test]
+    fn light_and_bsdf_sampling_converge_to_the_same_image() {
+        // Large lights just outside the view, so every pixel is lit only through the BSDFs and
+        // sampling them finds the lights often enough to converge quickly
+        let left_light = Sphere {center: Vec3::new(-3.5, 3.0, 0.0), radius: 1.0, material: Arc::new(DiffuseLight::new(Color01::new(1.0, 1.0, 1.0), 2.0))};
+        let right_light = Sphere {center: Vec3::new(3.5, 2.5, -0.5), radius: 1.2, material: Arc::new(DiffuseLight::new(Color01::new(1.0, 0.9, 0.8), 1.0))};
+        let glossy = Principled {metallic: texture::constant(1.0), roughness: texture::constant(0.2), clearcoat: texture::constant(1.0), clearcoat_gloss: texture::constant(0.3), ..Principled::default()};
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere {center: Vec3::new(0.0, -100.0, 0.0), radius: 100.0, material: Arc::new(Lambertian::new(Color01::new(0.5, 0.5, 0.5)))}));
+        world.add(Box::new(Sphere {center: Vec3::new(0.0, 1.0, 0.0), radius: 1.0, material: Arc::new(glossy)}));
+        world.add(Box::new(left_light.clone()));
+        world.add(Box::new(right_light.clone()));
+        let mut lights = HittableList::new();
+        lights.add(Box::new(left_light));
+        lights.add(Box::new(right_light));
+        
+        let mean_radiance = |light_sampling: LightSampling| {
+            let mut camera = Camera {
+                position: Vec3::new(0.0, 6.0, 1.5),
+                look_at: Vec3::new(0.0, 0.5, 0.0),
+                fov_vertical: 40.0,
+                background: Background::Black,
+                frame_width: 40,
+                aspect_ratio: 40.0 / 24.0,
+                samples_per_pixel: 512,
+                ray_bounces_max: 8,
+                light_sampling,
+                seed: 1,
+                show_progress: false,
+                ..Camera::default()
+            };
+            
+            let image = camera.render(&world, &lights, &[]);
+            let sum = image.pixels.iter().fold(Color01::default(), |sum, pixel| sum + *pixel);
+            
+            sum.luminance() / image.pixels.len() as f64
+        };
+        
+        // The glossy sphere is where BSDF sampling wins and the floor is where light sampling does, so
+        // both MIS weights matter. The single strategies keep around a percent of noise at this count
+        let mis = mean_radiance(LightSampling::Mis);
+        let light = mean_radiance(LightSampling::Light);
+        let bsdf = mean_radiance(LightSampling::Bsdf);
+        assert!((light / mis - 1.0).abs() < 0.03, "light sampling {}, mis {}", light, mis);
+        assert!((bsdf / mis - 1.0).abs() < 0.03, "bsdf sampling {}, mis {}", bsdf, mis);
+    }
+    
+    #[test]
+    fn oversized_frames_fail_validation() {
+        let camera = |frame_width: u32, aspect_ratio: f64| Camera {frame_width, aspect_ratio, ..Camera::default()};
+        
+        assert!(camera(1920, 16.0 / 9.0).validate().is_ok());
+        assert!(camera(16384, 1.0).validate().is_ok());
+        assert!(camera(70000, 1.0).validate().is_err());
+        assert!(camera(16385, 1.0).validate().is_err());
+        // Frame height saturates to u32::MAX
+        assert!(camera(2, 1e-30).validate().is_err());
+    }
+}
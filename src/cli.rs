@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use clap::{value_parser, Parser};
+
+use crate::camera::LightSampling;
+use crate::image::ImageFormat;
+use crate::scene::Scene;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "A path tracer following the Raytracing in One Weekend series")]
+pub struct Args {
+    /// TOML scene description, the built-in random spheres scene is rendered when omitted
+    pub scene: Option<PathBuf>,
+    
+    /// Output image path, overrides the scene's output path
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    
+    /// Output image format: png, ppm or ppm-ascii. Guessed from the output extension when omitted
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<ImageFormat>,
+    
+    /// Frame width in pixels
+    #[arg(short, long, value_parser = value_parser!(u32).range(1..))]
+    pub width: Option<u32>,
+    
+    /// Frame aspect ratio, either a number or width:height (e.g. 16:9)
+    #[arg(short, long, value_parser = parse_aspect_ratio)]
+    pub aspect: Option<f64>,
+    
+    /// Samples per pixel
+    #[arg(short, long = "spp", value_parser = value_parser!(u16).range(1..))]
+    pub samples: Option<u16>,
+    
+    /// Maximum number of ray bounces
+    #[arg(short = 'd', long, value_parser = value_parser!(u16).range(1..))]
+    pub max_depth: Option<u16>,
+    
+    /// Number of bounces before paths start being terminated by Russian roulette
+    #[arg(long)]
+    pub roulette_depth: Option<u16>,
+    
+    /// Number of render threads, all cores are used when omitted
+    #[arg(short = 'j', long, value_parser = value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+    
+    /// Seed for every random draw, the same seed always renders the same image
+    #[arg(long)]
+    pub seed: Option<u64>,
+    
+    /// How direct light is sampled: bsdf, light or mis
+    #[arg(long, value_parser = parse_light_sampling)]
+    pub light_sampling: Option<LightSampling>,
+    
+    /// Render spectrally, following each path at a few wavelengths instead of RGB
+    #[arg(long)]
+    pub spectral: bool,
+    
+    /// Hide the progress bar
+    #[arg(short, long)]
+    pub quiet: bool
+}
+
+impl Args {
+    // Command line values win over whatever the scene set
+    pub fn apply(&self, scene: &mut Scene) {
+        let camera = &mut scene.camera;
+        
+        if let Some(width) = self.width {
+            camera.frame_width = width;
+        }
+        if let Some(aspect) = self.aspect {
+            camera.aspect_ratio = aspect;
+        }
+        if let Some(samples) = self.samples {
+            camera.samples_per_pixel = samples;
+        }
+        if let Some(max_depth) = self.max_depth {
+            camera.ray_bounces_max = max_depth;
+        }
+        if let Some(roulette_depth) = self.roulette_depth {
+            camera.roulette_depth = roulette_depth;
+        }
+        if let Some(threads) = self.threads {
+            camera.thread_count = threads as usize;
+        }
+        if let Some(seed) = self.seed {
+            camera.seed = seed;
+        }
+        if let Some(light_sampling) = self.light_sampling {
+            camera.light_sampling = light_sampling;
+        }
+        if self.spectral {
+            camera.spectral = true;
+        }
+        camera.show_progress = !self.quiet;
+        
+        if let Some(output) = &self.output {
+            scene.output.format = ImageFormat::from_extension(output).unwrap_or(scene.output.format);
+            scene.output.path = output.clone();
+        }
+        if let Some(format) = self.format {
+            scene.output.format = format;
+        }
+    }
+}
+
+fn parse_format(value: &str) -> Result<ImageFormat, String> {
+    value.parse()
+}
+
+fn parse_light_sampling(value: &str) -> Result<LightSampling, String> {
+    value.parse()
+}
+
+fn parse_aspect_ratio(value: &str) -> Result<f64, String> {
+    let ratio = match value.split_once(':') {
+        Some((width, height)) => {
+            let width: f64 = width.trim().parse().map_err(|_| format!("invalid aspect ratio width '{}'", width))?;
+            let height: f64 = height.trim().parse().map_err(|_| format!("invalid aspect ratio height '{}'", height))?;
+            width / height
+        },
+        None => value.trim().parse().map_err(|_| format!("invalid aspect ratio '{}'", value))?
+    };
+    
+    if !ratio.is_finite() || ratio <= 0.0 {
+        return Err(format!("aspect ratio must be a positive number, got '{}'", value));
+    }
+    
+    Ok(ratio)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn aspect_ratio_accepts_ratios_and_numbers() {
+        assert_eq!(parse_aspect_ratio("16:9"), Ok(16.0 / 9.0));
+        assert_eq!(parse_aspect_ratio(" 4 : 3 "), Ok(4.0 / 3.0));
+        assert_eq!(parse_aspect_ratio("1.5"), Ok(1.5));
+    }
+    
+    #[test]
+    fn aspect_ratio_rejects_degenerate_and_garbage_values() {
+        assert!(parse_aspect_ratio("0:1").is_err());
+        assert!(parse_aspect_ratio("1:0").is_err());
+        assert!(parse_aspect_ratio("-2").is_err());
+        assert!(parse_aspect_ratio("wide").is_err());
+        assert!(parse_aspect_ratio("16:nine").is_err());
+        assert!(parse_aspect_ratio("").is_err());
+    }
+}
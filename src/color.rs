@@ -110,7 +110,54 @@ impl Color01 {
         Color01 {r, g, b}
     }
     
-    pub fn linear_to_gamma(&self) -> Color01{
+    // Relative luminance of linear Rec.709 primaries
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+    
+    pub fn max_component(&self) -> f64 {
+        self.r.max(self.g).max(self.b)
+    }
+    
+    // Linear sRGB color of a blackbody at a luminance of one, from the cubic fit of the Planckian
+    // locus by Kang et al. (2002), valid between 1667K and 25000K
+    pub fn blackbody(kelvin: f64) -> Color01 {
+        let t = kelvin.clamp(1667.0, 25000.0);
+        let (t2, t3) = (t * t, t * t * t);
+        
+        let x = if t <= 4000.0 {
+            -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+        }
+        else {
+            -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+        };
+        let (x2, x3) = (x * x, x * x * x);
+        
+        let y = if t <= 2222.0 {
+            -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+        }
+        else if t <= 4000.0 {
+            -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+        }
+        else {
+            3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+        };
+        
+        let color = Color01::from_xyz(x / y, 1.0, (1.0 - x - y) / y);
+        
+        Color01::new(color.r.max(0.0), color.g.max(0.0), color.b.max(0.0))
+    }
+    
+    // CIE XYZ to linear sRGB, colors outside the sRGB gamut come out with negative components
+    pub fn from_xyz(x: f64, y: f64, z: f64) -> Color01 {
+        Color01 {
+            r: 3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+            g: -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+            b: 0.0556434 * x - 0.2040259 * y + 1.0572252 * z
+        }
+    }
+    
+    pub fn linear_to_gamma(&self) -> Color01 {
         let mut ret = Color01::default();
         
         if self.r > 0.0 {
@@ -125,14 +172,40 @@ impl Color01 {
         
         ret
     }
+    
+    pub fn linear_to_srgb(&self) -> Color01 {
+        Color01 {
+            r: linear_to_srgb(self.r),
+            g: linear_to_srgb(self.g),
+            b: linear_to_srgb(self.b)
+        }
+    }
+    
+    pub fn srgb_to_linear(&self) -> Color01 {
+        Color01 {
+            r: srgb_to_linear(self.r),
+            g: srgb_to_linear(self.g),
+            b: srgb_to_linear(self.b)
+        }
+    }
 }
 
-fn print_color(color: Color) {
-    println!("{} {} {}", color.r.clamp(0, 255), color.g.clamp(0, 255), color.b.clamp(0, 255));
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        return value / 12.92;
+    }
+    
+    ((value + 0.055) / 1.055).powf(2.4)
 }
 
-pub fn print_color_01(color: Color01) {
-    let color_byte = Color::from(color.linear_to_gamma());
+fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0 {
+        return 0.0;
+    }
+    
+    if value <= 0.0031308 {
+        return value * 12.92;
+    }
     
-    print_color(color_byte);
+    1.055 * value.powf(1.0 / 2.4) - 0.055
 }
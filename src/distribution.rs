@@ -0,0 +1,122 @@
+// Piecewise constant density over [0, 1) proportional to `func`, sampled by inverting its CDF. An all
+// zero function falls back to a uniform density
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    // Integral of the function over [0, 1)
+    integral: f64
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Distribution1D {
+        let count = func.len().max(1) as f64;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for (i, value) in func.iter().enumerate() {
+            cdf.push(cdf[i] + value.max(0.0) / count);
+        }
+        
+        let integral = *cdf.last().unwrap();
+        for (i, value) in cdf.iter_mut().enumerate() {
+            *value = if integral > 0.0 { *value / integral } else { i as f64 / count };
+        }
+        
+        Distribution1D {func, cdf, integral}
+    }
+    
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+    
+    // Turns a uniform number into a sample in [0, 1), returned with its density and the piece it fell in
+    pub fn sample(&self, xi: f64) -> (f64, f64, usize) {
+        let count = self.func.len();
+        let offset = (self.cdf.partition_point(|value| *value <= xi).max(1) - 1).min(count - 1);
+        
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let within = if width > 0.0 { (xi - self.cdf[offset]) / width } else { 0.0 };
+        
+        ((offset as f64 + within) / count as f64, self.pdf(offset), offset)
+    }
+    
+    // Density of any sample inside the piece
+    pub fn pdf(&self, offset: usize) -> f64 {
+        if self.integral > 0.0 { self.func[offset].max(0.0) / self.integral } else { 1.0 }
+    }
+}
+
+// Piecewise constant density over the unit square from a row-major grid of values, a row is picked
+// from the marginal distribution and then a column from that row's conditional one
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func.chunks(width).take(height).map(|row| Distribution1D::new(row.to_vec())).collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.integral()).collect());
+        
+        Distribution2D {conditional, marginal}
+    }
+    
+    // Returns (u, v) with u along the rows and v down the columns, and its density on the square
+    pub fn sample(&self, xi_u: f64, xi_v: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample(xi_v);
+        let (u, pdf_u, _) = self.conditional[row].sample(xi_u);
+        
+        ((u, v), pdf_u * pdf_v)
+    }
+    
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let rows = self.conditional.len();
+        let row = ((v * rows as f64) as usize).min(rows - 1);
+        let columns = self.conditional[row].func.len();
+        let column = ((u * columns as f64) as usize).min(columns - 1);
+        
+        self.marginal.pdf(row) * self.conditional[row].pdf(column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // The pdf is constant over each cell of the grid, so a midpoint sum on a finer aligned grid is exact
+    fn integrate_pdf(distribution: &Distribution2D, steps: usize) -> f64 {
+        let step = 1.0 / steps as f64;
+        let mut sum = 0.0;
+        for j in 0..steps {
+            for i in 0..steps {
+                sum += distribution.pdf((i as f64 + 0.5) * step, (j as f64 + 0.5) * step) * step * step;
+            }
+        }
+        
+        sum
+    }
+    
+    #[test]
+    fn pdf_integrates_to_one() {
+        let func = [0.0, 1.0, 2.0, 0.5, 3.0, 0.0, 0.0, 0.0, 0.0, 0.25, 8.0, 1.0];
+        let distribution = Distribution2D::new(&func, 4, 3);
+        assert!((integrate_pdf(&distribution, 120) - 1.0).abs() < 1e-9);
+        
+        // All zero falls back to uniform
+        let distribution = Distribution2D::new(&[0.0; 6], 3, 2);
+        assert!((integrate_pdf(&distribution, 60) - 1.0).abs() < 1e-9);
+    }
+    
+    #[test]
+    fn sample_density_matches_pdf() {
+        let func = [0.0, 1.0, 2.0, 0.5, 3.0, 0.0, 0.0, 0.0, 0.0, 0.25, 8.0, 1.0];
+        let distribution = Distribution2D::new(&func, 4, 3);
+        
+        for (xi_u, xi_v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.95), (0.33, 0.71)] {
+            let ((u, v), pdf) = distribution.sample(xi_u, xi_v);
+            assert!(pdf > 0.0);
+            assert!((pdf - distribution.pdf(u, v)).abs() < 1e-9, "sampled pdf {} at ({}, {})", pdf, u, v);
+        }
+    }
+}
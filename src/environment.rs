@@ -0,0 +1,95 @@
+use std::fmt;
+
+use crate::color::Color01;
+use crate::distribution::Distribution2D;
+use crate::image::Image;
+use crate::matrix::Mat4;
+use crate::rt_util;
+use crate::vector::*;
+
+// Equirectangular image of the light arriving from every direction. The top row looks straight up and
+// u runs around the horizon. Directions are importance sampled in proportion to the pixels' luminance,
+// so small bright areas like the sun are found by light sampling instead of by chance
+pub struct EnvironmentMap {
+    image: Image,
+    intensity: f64,
+    // Rotation around the y axis, and its inverse to look directions up in the image
+    to_world: Mat4,
+    to_map: Mat4,
+    distribution: Distribution2D
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Image, rotation: f64, intensity: f64) -> EnvironmentMap {
+        let width = image.width as usize;
+        let height = image.height as usize;
+        
+        // Rows near the poles cover less solid angle, the sine of their polar angle makes up for that
+        let func: Vec<f64> = image.pixels.iter()
+            .enumerate()
+            .map(|(index, pixel)| {
+                let theta = rt_util::PI * ((index / width) as f64 + 0.5) / height as f64;
+                
+                pixel.luminance().max(0.0) * theta.sin()
+            })
+            .collect();
+        
+        let to_world = Mat4::rotation(&Vec3::new(0.0, 1.0, 0.0), rotation);
+        
+        EnvironmentMap {
+            distribution: Distribution2D::new(&func, width, height),
+            image,
+            intensity,
+            to_world,
+            to_map: to_world.transposed()
+        }
+    }
+    
+    pub fn radiance(&self, direction: &Vec3) -> Color01 {
+        let (u, v) = self.map_coords(direction);
+        let x = ((u * self.image.width as f64) as usize).min(self.image.width as usize - 1);
+        let y = ((v * self.image.height as f64) as usize).min(self.image.height as usize - 1);
+        
+        self.image.pixels[y * self.image.width as usize + x] * self.intensity
+    }
+    
+    pub fn sample_direction(&self) -> Vec3 {
+        let ((u, v), _) = self.distribution.sample(rt_util::random(), rt_util::random());
+        let phi = 2.0 * rt_util::PI * u;
+        let theta = rt_util::PI * v;
+        
+        let local = Vec3::new(-theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+        
+        self.to_world.transform_vector(&local)
+    }
+    
+    // Solid angle density of sample_direction(), the image's density divided by the Jacobian of the
+    // equirectangular mapping, 2 pi^2 sin(theta)
+    pub fn pdf(&self, direction: &Vec3) -> f64 {
+        let (u, v) = self.map_coords(direction);
+        let sin_theta = (rt_util::PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        
+        self.distribution.pdf(u, v) / (2.0 * rt_util::PI * rt_util::PI * sin_theta)
+    }
+    
+    fn map_coords(&self, direction: &Vec3) -> (f64, f64) {
+        let local = self.to_map.transform_vector(direction).normalized();
+        let theta = local.y.clamp(-1.0, 1.0).acos();
+        let phi = f64::atan2(-local.z, local.x) + rt_util::PI;
+        
+        (phi / (2.0 * rt_util::PI), theta / rt_util::PI)
+    }
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvironmentMap")
+            .field("width", &self.image.width)
+            .field("height", &self.image.height)
+            .field("intensity", &self.intensity)
+            .finish()
+    }
+}
@@ -0,0 +1,441 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::color::*;
+
+// Largest image decoded from a file, header sizes come from untrusted input
+const MAX_IMAGE_PIXELS: usize = 1 << 28;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    PpmAscii,
+    PpmBinary
+}
+
+impl ImageFormat {
+    pub fn from_extension(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::PpmBinary),
+            _ => None
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+    
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "ppm" | "p6" => Ok(ImageFormat::PpmBinary),
+            "ppm-ascii" | "p3" => Ok(ImageFormat::PpmAscii),
+            _ => Err(format!("unknown image format '{}', expected png, ppm or ppm-ascii", name))
+        }
+    }
+}
+
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color01>
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![Color01::default(); width as usize * height as usize]
+        }
+    }
+    
+    // Decodes any 8 or 16-bit PNG into linear colors, alpha is dropped
+    pub fn load_png(path: &Path) -> io::Result<Image> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        
+        let mut reader = decoder.read_info().map_err(io::Error::other)?;
+        let mut bytes = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut bytes).map_err(io::Error::other)?;
+        
+        let channels = frame.color_type.samples();
+        let to_linear = |byte: u8| Color01::from(Color {r: byte, g: byte, b: byte}).srgb_to_linear().r;
+        
+        let pixels = bytes[..frame.buffer_size()]
+            .chunks(channels)
+            .map(|texel| match channels {
+                1 | 2 => {
+                    let gray = to_linear(texel[0]);
+                    Color01::new(gray, gray, gray)
+                },
+                _ => Color01::new(to_linear(texel[0]), to_linear(texel[1]), to_linear(texel[2]))
+            })
+            .collect();
+        
+        Ok(Image {
+            width: frame.width,
+            height: frame.height,
+            pixels
+        })
+    }
+    
+    // Picks the HDR loader from the extension, .pfm or .hdr
+    pub fn load_hdr(path: &Path) -> io::Result<Image> {
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_ascii_lowercase();
+        let decode = match extension.as_str() {
+            "pfm" => decode_pfm,
+            "hdr" => decode_radiance,
+            _ => return Err(invalid_data("HDR images must be .hdr or .pfm files"))
+        };
+        
+        let mut bytes = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+        
+        decode(&bytes)
+    }
+    
+    pub fn write(&self, path: &Path, format: ImageFormat) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        
+        match format {
+            ImageFormat::Png => self.write_png(&mut writer)?,
+            ImageFormat::PpmAscii => self.write_ppm_ascii(&mut writer)?,
+            ImageFormat::PpmBinary => self.write_ppm_binary(&mut writer)?
+        }
+        
+        writer.flush()
+    }
+    
+    // Quantizes the linear framebuffer to 8 bits through `encode`, three bytes per pixel in row order
+    fn to_bytes(&self, encode: fn(&Color01) -> Color01) -> Vec<u8> {
+        self.pixels.iter()
+            .flat_map(|color| {
+                let color_byte = Color::from(encode(color));
+                
+                [color_byte.r, color_byte.g, color_byte.b]
+            })
+            .collect()
+    }
+    
+    // PPM keeps the square root gamma the renderer always wrote, so existing renders keep their tone.
+    // PNG declares sRGB in its header and gets the matching curve
+    fn to_ppm_bytes(&self) -> Vec<u8> {
+        self.to_bytes(Color01::linear_to_gamma)
+    }
+    
+    fn write_png<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        
+        let mut png_writer = encoder.write_header().map_err(io::Error::other)?;
+        png_writer.write_image_data(&self.to_bytes(Color01::linear_to_srgb)).map_err(io::Error::other)?;
+        
+        png_writer.finish().map_err(io::Error::other)
+    }
+    
+    fn write_ppm_ascii<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "P3")?;
+        writeln!(writer, "{} {}", self.width, self.height)?;
+        writeln!(writer, "255")?;
+        
+        for rgb in self.to_ppm_bytes().chunks(3) {
+            writeln!(writer, "{} {} {}", rgb[0], rgb[1], rgb[2])?;
+        }
+        
+        Ok(())
+    }
+    
+    fn write_ppm_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        
+        writer.write_all(&self.to_ppm_bytes())
+    }
+}
+
+// Portable float map: "PF" for RGB or "Pf" for grayscale, width, height and a scale whose sign gives
+// the byte order, then float32 rows from the bottom of the image up
+fn decode_pfm(bytes: &[u8]) -> io::Result<Image> {
+    let (tokens, data_start) = header_tokens(bytes, 4).ok_or_else(|| invalid_data("truncated PFM header"))?;
+    
+    let channels = match tokens[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid_data("not a PFM file"))
+    };
+    let width: u32 = tokens[1].parse().map_err(|_| invalid_data("invalid PFM width"))?;
+    let height: u32 = tokens[2].parse().map_err(|_| invalid_data("invalid PFM height"))?;
+    let scale: f64 = tokens[3].parse().map_err(|_| invalid_data("invalid PFM scale"))?;
+    
+    let pixel_count = checked_pixel_count(width, height).ok_or_else(|| invalid_data("PFM size is zero or too large"))?;
+    let row_len = width as usize * channels;
+    let data = &bytes[data_start..];
+    if data.len() / 4 < pixel_count * channels {
+        return Err(invalid_data("PFM file ends before its pixel data does"));
+    }
+    
+    let floats: Vec<f64> = data.chunks_exact(4)
+        .take(row_len * height as usize)
+        .map(|chunk| {
+            let chunk = chunk.try_into().unwrap();
+            let value = if scale < 0.0 { f32::from_le_bytes(chunk) } else { f32::from_be_bytes(chunk) };
+            
+            value as f64
+        })
+        .collect();
+    
+    let pixels = floats.chunks(row_len)
+        .rev()
+        .flat_map(|row| row.chunks(channels))
+        .map(|texel| if channels == 1 { Color01::new(texel[0], texel[0], texel[0]) } else { Color01::new(texel[0], texel[1], texel[2]) })
+        .collect();
+    
+    Ok(Image {width, height, pixels})
+}
+
+// Radiance RGBE: text header lines up to a blank one, a "-Y height +X width" resolution line, then
+// scanlines that are either flat RGBE quadruples or run-length encoded one channel at a time
+fn decode_radiance(bytes: &[u8]) -> io::Result<Image> {
+    let mut lines = bytes.split(|byte| *byte == b'\n');
+    let mut position = 0;
+    let mut next_line = || {
+        let line = lines.next()?;
+        position += line.len() + 1;
+        
+        Some(String::from_utf8_lossy(line).trim().to_string())
+    };
+    
+    let magic = next_line().ok_or_else(|| invalid_data("empty file"))?;
+    if magic != "#?RADIANCE" && magic != "#?RGBE" {
+        return Err(invalid_data("not a Radiance HDR file"));
+    }
+    
+    loop {
+        let line = next_line().ok_or_else(|| invalid_data("truncated Radiance header"))?;
+        if line.is_empty() {
+            break;
+        }
+        
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid_data("only RGBE Radiance files are supported"));
+            }
+        }
+    }
+    
+    let resolution = next_line().ok_or_else(|| invalid_data("missing Radiance resolution"))?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (height.parse::<u32>(), width.parse::<u32>()),
+        _ => return Err(invalid_data("only top to bottom, left to right Radiance images are supported"))
+    };
+    let (height, width) = match (height, width) {
+        (Ok(height), Ok(width)) => (height, width),
+        _ => return Err(invalid_data("invalid Radiance resolution"))
+    };
+    
+    checked_pixel_count(width, height).ok_or_else(|| invalid_data("Radiance image size is zero or too large"))?;
+    let truncated = || invalid_data("Radiance file ends before its pixel data does");
+    
+    // The smallest a scanline gets is flat texels, or its 4 byte head and a 2 byte run of 127 texels
+    // per channel when encoded, so the header can't ask for more than the data holds before anything
+    // is allocated
+    let data = &bytes[position.min(bytes.len())..];
+    let flat_len = 4 * width as usize;
+    let min_scanline_len = if (8..32768).contains(&width) { flat_len.min(4 + 8 * (width as usize).div_ceil(127)) } else { flat_len };
+    if data.len() / min_scanline_len < height as usize {
+        return Err(truncated());
+    }
+    
+    let mut data = data.iter().copied();
+    let mut pixels = Vec::new();
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    
+    for _ in 0..height {
+        let head: Vec<u8> = data.by_ref().take(4).collect();
+        if head.len() < 4 {
+            return Err(truncated());
+        }
+        
+        let encoded = (8..32768).contains(&width) && head[0] == 2 && head[1] == 2 && head[2] & 0x80 == 0;
+        if encoded {
+            if ((head[2] as u32) << 8 | head[3] as u32) != width {
+                return Err(invalid_data("Radiance scanline width mismatch"));
+            }
+            
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width as usize {
+                    let count = data.next().ok_or_else(truncated)? as usize;
+                    // Counts above 128 repeat the next byte, the others are followed by that many bytes
+                    let (run, repeated) = if count > 128 { (count - 128, Some(data.next().ok_or_else(truncated)?)) } else { (count, None) };
+                    if run == 0 || x + run > width as usize {
+                        return Err(invalid_data("corrupt Radiance scanline"));
+                    }
+                    
+                    for texel in scanline[x..x + run].iter_mut() {
+                        texel[channel] = match repeated {
+                            Some(value) => value,
+                            None => data.next().ok_or_else(truncated)?
+                        };
+                    }
+                    x += run;
+                }
+            }
+        }
+        else {
+            scanline[0].copy_from_slice(&head);
+            for texel in scanline[1..].iter_mut() {
+                for value in texel.iter_mut() {
+                    *value = data.next().ok_or_else(truncated)?;
+                }
+            }
+        }
+        
+        pixels.extend(scanline.iter().map(rgbe_to_color));
+    }
+    
+    Ok(Image {width, height, pixels})
+}
+
+fn checked_pixel_count(width: u32, height: u32) -> Option<usize> {
+    (width as usize).checked_mul(height as usize).filter(|count| *count > 0 && *count <= MAX_IMAGE_PIXELS)
+}
+
+// Shared exponent, each mantissa byte is scaled by 2^(e - 136)
+fn rgbe_to_color(rgbe: &[u8; 4]) -> Color01 {
+    if rgbe[3] == 0 {
+        return Color01::default();
+    }
+    
+    let scale = 2f64.powi(rgbe[3] as i32 - 136);
+    
+    Color01::new(rgbe[0] as f64 * scale, rgbe[1] as f64 * scale, rgbe[2] as f64 * scale)
+}
+
+// The first `count` whitespace separated header words and the offset just past the single whitespace
+// byte ending the last one
+fn header_tokens(bytes: &[u8], count: usize) -> Option<(Vec<String>, usize)> {
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    
+    while tokens.len() < count {
+        while bytes.get(position)?.is_ascii_whitespace() {
+            position += 1;
+        }
+        
+        let start = position;
+        while !bytes.get(position)?.is_ascii_whitespace() {
+            position += 1;
+        }
+        tokens.push(String::from_utf8_lossy(&bytes[start..position]).to_string());
+    }
+    
+    Some((tokens, position + 1))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Adaptive run length encoding of one channel of a scanline, runs of three or more equal bytes are
+    // repeated and the rest is written as literal bytes
+    fn encode_channel(values: &[u8], out: &mut Vec<u8>) {
+        let mut x = 0;
+        while x < values.len() {
+            let run = values[x..].iter().take(127).take_while(|value| **value == values[x]).count();
+            if run >= 3 {
+                out.extend_from_slice(&[128 + run as u8, values[x]]);
+                x += run;
+                continue;
+            }
+            
+            let mut end = x + 1;
+            while end < values.len() && end - x < 128 && !(end + 2 < values.len() && values[end] == values[end + 1] && values[end] == values[end + 2]) {
+                end += 1;
+            }
+            out.push((end - x) as u8);
+            out.extend_from_slice(&values[x..end]);
+            x = end;
+        }
+    }
+    
+    #[test]
+    fn decodes_run_length_encoded_radiance_files() {
+        let width = 10;
+        let rows: [Vec<[u8; 4]>; 2] = [
+            (0..width).map(|x| if x < 6 { [128, 64, 32, 129] } else { [x as u8 * 20, 7, 200, 130 + x as u8] }).collect(),
+            (0..width).map(|x| [x as u8, 255 - x as u8, 100, if x % 2 == 0 { 0 } else { 140 }]).collect()
+        ];
+        
+        let mut bytes = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", rows.len(), width).into_bytes();
+        for row in rows.iter() {
+            bytes.extend_from_slice(&[2, 2, 0, width as u8]);
+            for channel in 0..4 {
+                encode_channel(&row.iter().map(|texel| texel[channel]).collect::<Vec<u8>>(), &mut bytes);
+            }
+        }
+        
+        let image = decode_radiance(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (width, 2));
+        assert_eq!(image.pixels[0], Color01::new(1.0, 0.5, 0.25));
+        
+        let expected: Vec<Color01> = rows.iter().flatten().map(rgbe_to_color).collect();
+        assert!(image.pixels == expected);
+    }
+    
+    #[test]
+    fn decodes_flat_radiance_scanlines() {
+        let mut bytes = b"#?RADIANCE\n\n-Y 1 +X 2\n".to_vec();
+        bytes.extend_from_slice(&[128, 64, 32, 129, 0, 0, 0, 0]);
+        
+        let image = decode_radiance(&bytes).unwrap();
+        assert!(image.pixels == vec![Color01::new(1.0, 0.5, 0.25), Color01::default()]);
+    }
+    
+    #[test]
+    fn truncated_radiance_data_is_an_error() {
+        let mut bytes = b"#?RADIANCE\n\n-Y 1 +X 10\n".to_vec();
+        bytes.extend_from_slice(&[2, 2, 0, 10, 138, 5]);
+        
+        assert!(decode_radiance(&bytes).is_err());
+    }
+    
+    #[test]
+    fn oversized_headers_are_an_error() {
+        for resolution in ["-Y 100000 +X 100000", "-Y 4294967295 +X 4294967295", "-Y 0 +X 16", "-Y 100 +X 16"] {
+            let bytes = format!("#?RADIANCE\n\n{}\n", resolution).into_bytes();
+            assert!(decode_radiance(&bytes).is_err(), "{}", resolution);
+        }
+        
+        for header in ["PF\n4294967295 4294967295\n-1.0\n", "PF\n0 4\n-1.0\n", "Pf\n100000 100000\n-1.0\n"] {
+            let mut bytes = header.as_bytes().to_vec();
+            bytes.extend_from_slice(&[0; 64]);
+            assert!(decode_pfm(&bytes).is_err(), "{}", header);
+        }
+    }
+    
+    #[test]
+    fn ppm_output_keeps_the_square_root_gamma() {
+        let mut image = Image::new(7, 1);
+        image.pixels = [0.0, -0.1, 0.01, 0.25, 0.5, 1.0, 4.0].map(|value| Color01::new(value, value, value)).to_vec();
+        
+        let mut bytes = Vec::new();
+        image.write_ppm_binary(&mut bytes).unwrap();
+        let expected: Vec<u8> = [0, 0, 25, 127, 181, 255, 255].iter().flat_map(|byte| [*byte; 3]).collect();
+        assert_eq!(bytes, [b"P6\n7 1\n255\n".as_slice(), &expected].concat());
+        
+        // PNG gets the sRGB curve it declares instead
+        assert_eq!(image.to_bytes(Color01::linear_to_srgb)[9..12], [137, 137, 137]);
+    }
+}
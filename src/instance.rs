@@ -0,0 +1,112 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::matrix::Mat4;
+use crate::primitive::*;
+use crate::ray::Ray;
+use crate::vector::*;
+
+// Places shared geometry in the world through an affine transform. Rays are moved into object space
+// instead of moving the geometry, so any number of instances can reference the same object
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    transform: Mat4,
+    inverse: Mat4,
+    // Inverse transpose, keeps normals perpendicular to the surface under non-uniform scaling
+    normal_matrix: Mat4,
+    bbox: Aabb
+}
+
+impl Instance {
+    // None when the transform can't be inverted, e.g. a zero scale
+    pub fn new(object: Arc<dyn Hittable>, transform: Mat4) -> Option<Self> {
+        let inverse = transform.inverse()?;
+        let bbox = transform_box(&object.bounding_box(), &transform);
+        
+        Some(Self {
+            object,
+            transform,
+            inverse,
+            normal_matrix: inverse.transposed(),
+            bbox
+        })
+    }
+    
+    fn to_object(&self, ray: &Ray) -> Ray {
+        Ray::new(self.inverse.transform_point(&ray.origin), self.inverse.transform_vector(&ray.direction), ray.time)
+    }
+    
+    fn to_world(&self, ray: &Ray, mut result: RayHitResult) -> RayHitResult {
+        if !result.is_hit {
+            return result;
+        }
+        
+        result.data.point = ray.at(result.data.ray_t);
+        // The normal already faces against the object space ray, the inverse transpose keeps that
+        result.data.normal = self.normal_matrix.transform_vector(&result.data.normal).normalized();
+        
+        result
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        // The direction isn't normalized so distances along the ray are the same in both spaces
+        self.to_world(ray, self.object.hit(&self.to_object(ray), t_range))
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+    
+    fn hit_surface(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        self.to_world(ray, self.object.hit_surface(&self.to_object(ray), t_range))
+    }
+    
+    fn transmittance(&self, ray: &Ray, t_range: Range<f64>) -> f64 {
+        self.object.transmittance(&self.to_object(ray), t_range)
+    }
+    
+    // The object's density is converted by the Jacobian of the direction change, |det A| / |A w|^3
+    // for a unit object space direction w
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f64) -> f64 {
+        let object_origin = self.inverse.transform_point(origin);
+        let object_direction = self.inverse.transform_vector(direction).normalized();
+        
+        let object_pdf = self.object.pdf_value(&object_origin, &object_direction, time);
+        if object_pdf <= 0.0 {
+            return 0.0;
+        }
+        
+        let stretch = self.transform.transform_vector(&object_direction).len();
+        
+        object_pdf * stretch * stretch * stretch / self.transform.linear_determinant().abs()
+    }
+    
+    fn random_direction(&self, origin: &Vec3, time: f64) -> Vec3 {
+        let object_direction = self.object.random_direction(&self.inverse.transform_point(origin), time);
+        
+        self.transform.transform_vector(&object_direction)
+    }
+}
+
+// Box around the eight transformed corners of an object space box
+fn transform_box(bbox: &Aabb, transform: &Mat4) -> Aabb {
+    if bbox.is_empty() {
+        return *bbox;
+    }
+    
+    let mut result = Aabb::empty();
+    for corner in 0..8 {
+        let point = Vec3::new(
+            if corner & 1 == 0 { bbox.min.x } else { bbox.max.x },
+            if corner & 2 == 0 { bbox.min.y } else { bbox.max.y },
+            if corner & 4 == 0 { bbox.min.z } else { bbox.max.z }
+        );
+        let transformed = transform.transform_point(&point);
+        result = Aabb::surrounding(&result, &Aabb::new(transformed, transformed));
+    }
+    
+    result
+}
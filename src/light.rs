@@ -0,0 +1,101 @@
+use crate::color::Color01;
+use crate::rt_util;
+use crate::vector::*;
+
+// Analytic lights without geometry. Rays can never hit them, so they are kept out of the world and
+// only reached by shadow rays
+#[derive(Clone, Debug)]
+pub enum DeltaLight {
+    // Shines equally in every direction with inverse-square falloff
+    Point {
+        position: Vec3,
+        intensity: Color01
+    },
+    // Point light limited to a cone around `direction`, fading out towards its edge
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Color01,
+        // Cosines of the cone's half angle and of the angle the fade starts at
+        cos_cone: f64,
+        cos_falloff_start: f64
+    },
+    // Parallel light travelling along `direction` from infinitely far away, `irradiance` is what a
+    // surface facing it receives
+    Directional {
+        direction: Vec3,
+        irradiance: Color01
+    }
+}
+
+// Light arriving at a point from a delta light, along the unit `direction` towards it
+pub struct DeltaLightSample {
+    pub direction: Vec3,
+    // Distance to the light, infinite for directional lights
+    pub distance: f64,
+    pub radiance: Color01
+}
+
+impl DeltaLight {
+    // Angles in degrees, the cone's half angle and the width of the soft edge inside it
+    pub fn spot(position: Vec3, direction: Vec3, intensity: Color01, cone_angle: f64, falloff_angle: f64) -> DeltaLight {
+        let cone_angle = cone_angle.clamp(0.0, 180.0);
+        let falloff_start = (cone_angle - falloff_angle.max(0.0)).max(0.0);
+        
+        DeltaLight::Spot {
+            position,
+            direction: direction.normalized(),
+            intensity,
+            cos_cone: rt_util::deg_to_rad(cone_angle).cos(),
+            cos_falloff_start: rt_util::deg_to_rad(falloff_start).cos()
+        }
+    }
+    
+    // None when the point gets no light, outside a spot light's cone or exactly at the light
+    pub fn illuminate(&self, point: &Vec3) -> Option<DeltaLightSample> {
+        match self {
+            DeltaLight::Point {position, intensity} => {
+                let (direction, distance) = towards(point, position)?;
+                
+                Some(DeltaLightSample {direction, distance, radiance: *intensity * (1.0 / (distance * distance))})
+            },
+            DeltaLight::Spot {position, direction: spot_direction, intensity, cos_cone, cos_falloff_start} => {
+                let (direction, distance) = towards(point, position)?;
+                let cos_theta = -dot(&direction, spot_direction);
+                let falloff = smooth_step(*cos_cone, *cos_falloff_start, cos_theta);
+                if falloff <= 0.0 {
+                    return None;
+                }
+                
+                Some(DeltaLightSample {direction, distance, radiance: *intensity * (falloff / (distance * distance))})
+            },
+            DeltaLight::Directional {direction, irradiance} => Some(DeltaLightSample {
+                direction: -direction.normalized(),
+                distance: rt_util::INFINITY,
+                radiance: *irradiance
+            })
+        }
+    }
+}
+
+fn towards(point: &Vec3, position: &Vec3) -> Option<(Vec3, f64)> {
+    let offset = *position - *point;
+    let distance = offset.len();
+    if distance <= 0.0 {
+        return None;
+    }
+    
+    Some((offset * (1.0 / distance), distance))
+}
+
+// 0 at or below `edge_0`, 1 at or above `edge_1` and a smooth cubic in between. A hard edge when both
+// are the same
+fn smooth_step(edge_0: f64, edge_1: f64, x: f64) -> f64 {
+    if edge_1 <= edge_0 {
+        return if x >= edge_0 { 1.0 } else { 0.0 };
+    }
+    
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    
+    t * t * (3.0 - 2.0 * t)
+}
@@ -1,18 +1,86 @@
+mod aabb;
+mod bvh;
 mod primitive;
 mod vector;
+mod matrix;
 mod camera;
+mod cli;
 mod color;
+mod image;
+mod distribution;
+mod environment;
+mod sky;
+mod light;
+mod spectrum;
 mod ray;
 mod rt_util;
+mod scene;
+mod texture;
 mod material;
+mod mesh;
+mod medium;
+mod voxel;
+mod instance;
+mod microfacet;
+mod principled;
+mod obj;
+mod planar;
+mod perlin;
 
+use bvh::BvhNode;
+use clap::Parser;
+use cli::Args;
 use color::Color01;
+use image::ImageFormat;
+use scene::*;
 use material::*;
 use primitive::*;
 use camera::*;
 use vector::*;
 
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::process;
+
 fn main() {
+    let args = Args::parse();
+    
+    let mut scene = match &args.scene {
+        Some(scene_path) => match load_scene(scene_path, args.seed) {
+            Ok(scene) => scene,
+            Err(error) => {
+                eprintln!("Failed to load scene: {}", error);
+                process::exit(1);
+            }
+        },
+        None => random_spheres_scene(args.seed.unwrap_or(0))
+    };
+    
+    args.apply(&mut scene);
+    
+    if let Err(message) = scene.camera.validate() {
+        eprintln!("Invalid render settings: {}", message);
+        process::exit(2);
+    }
+    
+    let mut camera = scene.camera;
+    let output = scene.output;
+    
+    let mut world: HittableList = HittableList::new();
+    world.add(Box::new(BvhNode::new(scene.world)));
+    
+    let image = camera.render(&world, &scene.lights, &scene.delta_lights);
+    
+    if let Err(error) = image.write(&output.path, output.format) {
+        eprintln!("Failed to write {}: {}", output.path.display(), error);
+        process::exit(1);
+    }
+}
+
+fn random_spheres_scene(scene_seed: u64) -> Scene {
+    let output_path = PathBuf::from("render.png");
+    let output_format = ImageFormat::from_extension(&output_path).unwrap_or(ImageFormat::Png);
+    
     let mut camera: Camera = Camera::default();
     
     camera.position = Vec3::new(13.0, 2.0, 3.0);
@@ -25,15 +93,18 @@ fn main() {
     camera.aspect_ratio = 16.0 / 9.0;
     camera.samples_per_pixel = 500; // 500
     camera.ray_bounces_max = 50; // 50
+    camera.seed = scene_seed;
+    
+    rt_util::seed_rng(scene_seed);
     
     let mut world: HittableList = HittableList::new();
     
-    let mat_ground = Material {type_info: MaterialType::Lambert, albedo: Color01::new(0.5, 0.3, 0.5), fuzziness: 0.0, refraction_idx: 1.0};
+    let mat_ground = Arc::new(Lambertian::new(Color01::new(0.5, 0.3, 0.5)));
     world.add(Box::new(Sphere {center: Vec3::new(0.0, -1000.0, 0.0), radius: 1000.0, material: mat_ground}));
     
-    let mat_1 = Material {type_info: MaterialType::Dielectric, albedo: Color01::default(), fuzziness: 0.0, refraction_idx: 1.5};
-    let mat_2 = Material {type_info: MaterialType::Lambert, albedo: Color01::new(0.4, 0.2, 0.1), fuzziness: 0.0, refraction_idx: 1.0};
-    let mat_3 = Material {type_info: MaterialType::Metal, albedo: Color01::new(0.7, 0.6, 0.5), fuzziness: 0.0, refraction_idx: 1.0};
+    let mat_1 = Arc::new(Dielectric::new(1.5));
+    let mat_2 = Arc::new(Lambertian::new(Color01::new(0.4, 0.2, 0.1)));
+    let mat_3 = Arc::new(Metal::new(Color01::new(0.7, 0.6, 0.5), 0.0));
     
     world.add(Box::new(Sphere {center: Vec3::new(0.0, 1.0, 0.0), radius: 1.0, material: mat_1}));
     world.add(Box::new(Sphere {center: Vec3::new(-4.0, 1.0, 0.0), radius: 1.0, material: mat_2}));
@@ -44,23 +115,23 @@ fn main() {
             let choose_mat = rt_util::random();
             let position = Vec3::new(a as f64 + 0.9 * rt_util::random(), 0.2, b as f64 + 0.9 * rt_util::random());
             
-            let mat: Material;
+            let mat: Arc<dyn Material>;
             if (position - Vec3::new(4.0, 0.2, 0.0)).len() > 0.9 {
                 if choose_mat < 0.8 {
                     let color_vec = Vec3::random_range(0.0..1.0);
                     let color = Color01::new(color_vec.x, color_vec.y, color_vec.z);
                     
-                    mat = Material {type_info: MaterialType::Lambert, albedo: color, fuzziness: 0.0, refraction_idx: 1.0};
+                    mat = Arc::new(Lambertian::new(color));
                 }
                 else if choose_mat < 0.95 {
                     let color_vec = Vec3::random_range(0.5..1.0);
                     let color = Color01::new(color_vec.x, color_vec.y, color_vec.z);
                     let fuzz = rt_util::random() * 0.5;
                     
-                    mat = Material {type_info: MaterialType::Metal, albedo: color, fuzziness: fuzz, refraction_idx: 1.0};
+                    mat = Arc::new(Metal::new(color, fuzz));
                 }
                 else {
-                    mat = Material {type_info: MaterialType::Dielectric, albedo: Color01::default(), fuzziness: 0.0, refraction_idx: 1.5};
+                    mat = Arc::new(Dielectric::new(1.5));
                 }
                 
                 world.add(Box::new(Sphere {center: position, radius: 0.2, material: mat }));
@@ -68,5 +139,11 @@ fn main() {
         }
     }
     
-    camera.render(&world);
+    Scene {
+        camera,
+        world,
+        lights: HittableList::new(),
+        delta_lights: Vec::new(),
+        output: OutputSettings {path: output_path, format: output_format}
+    }
 }
@@ -1,82 +1,443 @@
+use std::fmt;
 use std::ops::Neg;
+use std::sync::Arc;
 
 use crate::primitive::RayHitResult;
 use crate::color::Color01;
+use crate::microfacet::*;
 use crate::rt_util;
+use crate::spectrum::{ComplexIor, Dispersion, Wavelengths, D_LINE};
+use crate::texture::Texture;
 use crate::vector::*;
 use crate::ray::*;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum MaterialType {
-    Lambert,
-    Metal,
-    Dielectric
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub struct Material {
-    pub type_info: MaterialType,
-    pub albedo: Color01,
-    pub fuzziness: f64,
-    pub refraction_idx: f64
-}
-
-impl Default for Material {
-    fn default() -> Self {
-        Material {type_info: MaterialType::Lambert, albedo: Color01::default(), fuzziness: 0.0, refraction_idx: 1.0}
-    }
-}
-
-impl Material {
-    pub fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> (Ray, Color01, bool) {
-        match self.type_info {
-            MaterialType::Lambert => {
-                let mut scatter_dir = hit.data.normal + rt_util::random_in_unit_sphere().normalized();
-                if scatter_dir.near_zero() {
-                    scatter_dir = hit.data.normal;
-                }
-                
-                let scattered_ray = Ray::new(hit.data.point, scatter_dir);
-                let attenuation = self.albedo;
-                
-                (scattered_ray, attenuation, true)
-            },
-            MaterialType::Metal => {
-                let reflection_dir = ray.direction.reflect(&hit.data.normal) + self.fuzziness * rt_util::random_in_unit_sphere().normalized();
-                
-                let scattered_ray = Ray::new(hit.data.point, reflection_dir.normalized());
-                let attenuation = self.albedo;
-                
-                (scattered_ray, attenuation, dot(&reflection_dir, &hit.data.normal) > 0.0)
-            },
-            MaterialType::Dielectric => {
-                let refr_factor = if hit.data.front_face {
-                    1.0 / self.refraction_idx
-                }
-                else {
-                    self.refraction_idx
-                };
-                
-                let ray_dir_norm = &ray.direction.normalized();
-                let cos_theta = f64::min(dot(&ray_dir_norm.neg(), &hit.data.normal), 1.0);
-                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-                
-                let out_dir: Vec3;
-                if sin_theta * refr_factor > 1.0 || schlick_reflectance(cos_theta, refr_factor) > rt_util::random() {
-                    out_dir = ray_dir_norm.reflect(&hit.data.normal);
-                }
-                else {
-                    out_dir = ray_dir_norm.refract(&hit.data.normal, refr_factor);
-                }
-                
-                let out_ray = Ray::new(hit.data.point, out_dir);
-                
-                (out_ray, Color01::new(1.0, 1.0, 1.0), true)
-            },
+// Outcome of a ray hitting a surface
+pub struct ScatterRecord {
+    pub emission: Color01,
+    // None when the surface absorbs the ray
+    pub scattered: Option<Ray>,
+    // BSDF times cosine over pdf for the scattered direction, what the path throughput is scaled by
+    pub attenuation: Color01,
+    // Solid angle density of the scattered direction, meaningless for specular records
+    pub pdf: f64,
+    // Delta distributions can't be hit by light sampling, their bounces always count emission fully
+    pub is_specular: bool,
+    // The scattered direction was picked for the ray's hero wavelength only, the other wavelengths
+    // of a spectral path would have gone elsewhere
+    pub dispersive: bool
+}
+
+impl ScatterRecord {
+    pub fn absorbed(emission: Color01) -> ScatterRecord {
+        ScatterRecord {emission, scattered: None, attenuation: Color01::default(), pdf: 0.0, is_specular: false, dispersive: false}
+    }
+    
+    pub fn specular(ray: Ray, attenuation: Color01) -> ScatterRecord {
+        ScatterRecord {emission: Color01::default(), scattered: Some(ray), attenuation, pdf: 0.0, is_specular: true, dispersive: false}
+    }
+    
+    pub fn diffuse(ray: Ray, attenuation: Color01, pdf: f64) -> ScatterRecord {
+        ScatterRecord {emission: Color01::default(), scattered: Some(ray), attenuation, pdf, is_specular: false, dispersive: false}
+    }
+}
+
+pub trait Material: fmt::Debug + Send + Sync {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord;
+    
+    fn emitted(&self, _hit: &RayHitResult) -> Color01 {
+        Color01::default()
+    }
+    
+    // Emissive materials are sampled directly as lights
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    
+    // Solid angle density of scatter() producing `direction`, zero for specular materials
+    fn scattering_pdf(&self, _ray: &Ray, _hit: &RayHitResult, _direction: &Vec3) -> f64 {
+        0.0
+    }
+    
+    // BSDF times the cosine term for light arriving from `direction`, used by light sampling
+    fn eval(&self, _ray: &Ray, _hit: &RayHitResult, _direction: &Vec3) -> Color01 {
+        Color01::default()
+    }
+    
+    // Spectral renders turn RGB attenuation and eval() results into spectra, spectral materials return
+    // them at the ray's wavelengths already
+    fn is_spectral(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Lambertian {
+    pub albedo: Arc<dyn Texture>
+}
+
+impl Lambertian {
+    pub fn new(albedo: Color01) -> Lambertian {
+        Lambertian {albedo: Arc::new(albedo)}
+    }
+}
+
+impl Material for Lambertian {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        let mut scatter_dir = hit.data.normal + rt_util::random_in_unit_sphere().normalized();
+        if scatter_dir.near_zero() {
+            scatter_dir = hit.data.normal;
+        }
+        
+        let pdf = dot(&scatter_dir.normalized(), &hit.data.normal) / rt_util::PI;
+        let scattered_ray = Ray::new(hit.data.point, scatter_dir, ray.time);
+        
+        ScatterRecord::diffuse(scattered_ray, albedo_at(&self.albedo, hit), pdf)
+    }
+    
+    fn scattering_pdf(&self, _ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> f64 {
+        let cosine = dot(&direction.normalized(), &hit.data.normal);
+        
+        f64::max(cosine, 0.0) / rt_util::PI
+    }
+    
+    fn eval(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> Color01 {
+        albedo_at(&self.albedo, hit) * self.scattering_pdf(ray, hit, direction)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Metal {
+    pub albedo: Arc<dyn Texture>,
+    pub fuzziness: f64
+}
+
+impl Metal {
+    pub fn new(albedo: Color01, fuzziness: f64) -> Metal {
+        Metal {albedo: Arc::new(albedo), fuzziness}
+    }
+}
+
+impl Material for Metal {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        let reflection = ray.direction.reflect(&hit.data.normal);
+        let reflection_dir = reflection + self.fuzziness * rt_util::random_in_unit_sphere().normalized();
+        if dot(&reflection_dir, &hit.data.normal) <= 0.0 {
+            return ScatterRecord::absorbed(Color01::default());
+        }
+        
+        let scattered_ray = Ray::new(hit.data.point, reflection_dir.normalized(), ray.time);
+        let attenuation = albedo_at(&self.albedo, hit);
+        
+        if self.fuzziness <= 0.0 {
+            return ScatterRecord::specular(scattered_ray, attenuation);
+        }
+        
+        let pdf = fuzzy_reflection_pdf(&reflection, self.fuzziness, &scattered_ray.direction);
+        
+        ScatterRecord::diffuse(scattered_ray, attenuation, pdf)
+    }
+    
+    fn scattering_pdf(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> f64 {
+        if self.fuzziness <= 0.0 || dot(direction, &hit.data.normal) <= 0.0 {
+            return 0.0;
+        }
+        
+        fuzzy_reflection_pdf(&ray.direction.reflect(&hit.data.normal), self.fuzziness, direction)
+    }
+    
+    // The fuzzy lobe carries the albedo as its sampling weight, so this is the albedo scaled by the pdf
+    fn eval(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> Color01 {
+        albedo_at(&self.albedo, hit) * self.scattering_pdf(ray, hit, direction)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Dielectric {
+    pub refraction_idx: f64,
+    // Spectral renders refract each hero wavelength by its own index, RGB ones use `refraction_idx`
+    pub dispersion: Option<Dispersion>
+}
+
+impl Dielectric {
+    pub fn new(refraction_idx: f64) -> Dielectric {
+        Dielectric {refraction_idx, dispersion: None}
+    }
+    
+    pub fn dispersive(dispersion: Dispersion) -> Dielectric {
+        Dielectric {refraction_idx: dispersion.refraction_idx(D_LINE), dispersion: Some(dispersion)}
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        let (refraction_idx, dispersive) = match (&self.dispersion, ray.wavelength) {
+            (Some(dispersion), Some(wavelength)) => (dispersion.refraction_idx(wavelength), true),
+            _ => (self.refraction_idx, false)
+        };
+        
+        let refr_factor = if hit.data.front_face {
+            1.0 / refraction_idx
+        }
+        else {
+            refraction_idx
+        };
+        
+        let ray_dir_norm = &ray.direction.normalized();
+        let cos_theta = f64::min(dot(&ray_dir_norm.neg(), &hit.data.normal), 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        
+        #[allow(clippy::needless_late_init)]
+        let out_dir: Vec3;
+        if sin_theta * refr_factor > 1.0 || schlick_reflectance(cos_theta, refr_factor) > rt_util::random() {
+            out_dir = ray_dir_norm.reflect(&hit.data.normal);
+        }
+        else {
+            out_dir = ray_dir_norm.refract(&hit.data.normal, refr_factor);
+        }
+        
+        let out_ray = Ray::new(hit.data.point, out_dir, ray.time);
+        
+        ScatterRecord {dispersive, ..ScatterRecord::specular(out_ray, Color01::new(1.0, 1.0, 1.0))}
+    }
+}
+
+// GGX microfacet metal described by its complex index of refraction per RGB channel
+#[derive(Clone, Debug)]
+pub struct Conductor {
+    pub eta: Color01,
+    pub k: Color01,
+    // Measured index that spectral renders use at each wavelength in place of eta and k
+    pub spectrum: Option<&'static ComplexIor>,
+    pub distribution: Ggx
+}
+
+impl Conductor {
+    pub fn new(eta: Color01, k: Color01, roughness: f64) -> Conductor {
+        Conductor {eta, k, spectrum: None, distribution: Ggx::from_roughness(roughness)}
+    }
+    
+    pub fn measured(spectrum: &'static ComplexIor, roughness: f64) -> Conductor {
+        let (eta, k) = spectrum.rgb();
+        
+        Conductor {eta, k, spectrum: Some(spectrum), distribution: Ggx::from_roughness(roughness)}
+    }
+    
+    fn ior(&self, ray: &Ray) -> (Color01, Color01) {
+        match (self.spectrum, ray.wavelength) {
+            (Some(spectrum), Some(hero)) => spectrum.at_wavelengths(Wavelengths::from_hero(hero)),
+            _ => (self.eta, self.k)
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        let frame = Onb::new(&hit.data.normal);
+        let wo = frame.world_to_local(&-ray.direction.normalized());
+        if wo.z <= 0.0 {
+            return ScatterRecord::absorbed(Color01::default());
+        }
+        
+        if self.distribution.is_smooth() {
+            let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+            let scattered_ray = Ray::new(hit.data.point, frame.local_to_world(&wi), ray.time);
+            
+            let (eta, k) = self.ior(ray);
+            
+            return ScatterRecord::specular(scattered_ray, fresnel_conductor(wo.z, &eta, &k));
+        }
+        
+        let m = self.distribution.sample_visible_normal(&wo);
+        let wi = (-wo).reflect(&m);
+        if wi.z <= 0.0 {
+            return ScatterRecord::absorbed(Color01::default());
+        }
+        
+        let cos_om = dot(&wo, &m);
+        let (eta, k) = self.ior(ray);
+        let fresnel = fresnel_conductor(cos_om, &eta, &k);
+        let attenuation = fresnel * (self.distribution.g2(&wo, &wi) / self.distribution.g1(&wo));
+        let pdf = self.distribution.visible_normal_pdf(&wo, &m) / (4.0 * cos_om);
+        
+        ScatterRecord::diffuse(Ray::new(hit.data.point, frame.local_to_world(&wi), ray.time), attenuation, pdf)
+    }
+    
+    fn scattering_pdf(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> f64 {
+        let (wo, wi) = local_directions(ray, hit, direction);
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        
+        let m = (wo + wi).normalized();
+        
+        self.distribution.visible_normal_pdf(&wo, &m) / (4.0 * dot(&wo, &m))
+    }
+    
+    fn eval(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> Color01 {
+        let (wo, wi) = local_directions(ray, hit, direction);
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color01::default();
+        }
+        
+        let m = (wo + wi).normalized();
+        let (eta, k) = self.ior(ray);
+        let fresnel = fresnel_conductor(dot(&wo, &m), &eta, &k);
+        
+        fresnel * (self.distribution.d(&m) * self.distribution.g2(&wo, &wi) / (4.0 * wo.z))
+    }
+    
+    fn is_spectral(&self) -> bool {
+        self.spectrum.is_some()
+    }
+}
+
+// GGX microfacet glass, reflects and refracts through the sampled microfacet
+#[derive(Clone, Debug)]
+pub struct RoughDielectric {
+    pub refraction_idx: f64,
+    pub distribution: Ggx
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_idx: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric {refraction_idx, distribution: Ggx::from_roughness(roughness)}
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        let frame = Onb::new(&hit.data.normal);
+        let wo = frame.world_to_local(&-ray.direction.normalized());
+        let eta = relative_idx(self.refraction_idx, hit);
+        
+        if self.distribution.is_smooth() {
+            let m = Vec3::new(0.0, 0.0, 1.0);
+            let wi = sample_dielectric_direction(&wo, &m, eta);
+            
+            // Choosing between the two lobes by the Fresnel term cancels it from the weight
+            return ScatterRecord::specular(Ray::new(hit.data.point, frame.local_to_world(&wi), ray.time), Color01::new(1.0, 1.0, 1.0));
+        }
+        
+        let wi = match self.distribution.sample_dielectric(&wo, eta) {
+            Some(wi) => wi,
+            None => return ScatterRecord::absorbed(Color01::default())
+        };
+        let pdf = self.distribution.dielectric_pdf(&wo, &wi, eta);
+        if pdf <= 0.0 {
+            return ScatterRecord::absorbed(Color01::default());
+        }
+        
+        let weight = self.distribution.g2(&wo, &wi) / self.distribution.g1(&wo);
+        
+        ScatterRecord::diffuse(Ray::new(hit.data.point, frame.local_to_world(&wi), ray.time), Color01::new(weight, weight, weight), pdf)
+    }
+    
+    fn scattering_pdf(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        
+        let (wo, wi) = local_directions(ray, hit, direction);
+        
+        self.distribution.dielectric_pdf(&wo, &wi, relative_idx(self.refraction_idx, hit))
+    }
+    
+    fn eval(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> Color01 {
+        if self.distribution.is_smooth() {
+            return Color01::default();
         }
+        
+        let (wo, wi) = local_directions(ray, hit, direction);
+        let value = self.distribution.dielectric_eval(&wo, &wi, relative_idx(self.refraction_idx, hit));
+        
+        Color01::new(value, value, value)
     }
 }
 
+// Emits light from its front face only and absorbs everything that hits it
+#[derive(Clone, Debug)]
+pub struct DiffuseLight {
+    pub emit: Arc<dyn Texture>,
+    pub intensity: f64
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color01, intensity: f64) -> DiffuseLight {
+        DiffuseLight {emit: Arc::new(emit), intensity}
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        ScatterRecord::absorbed(self.emitted(hit))
+    }
+    
+    fn emitted(&self, hit: &RayHitResult) -> Color01 {
+        if !hit.data.front_face {
+            return Color01::default();
+        }
+        
+        albedo_at(&self.emit, hit) * self.intensity
+    }
+    
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+// Index on the far side of the surface over the index on the side the ray comes from
+pub fn relative_idx(refraction_idx: f64, hit: &RayHitResult) -> f64 {
+    if hit.data.front_face {
+        refraction_idx
+    }
+    else {
+        1.0 / refraction_idx
+    }
+}
+
+// Outgoing and incoming directions in the shading frame, both pointing away from the surface
+pub fn local_directions(ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> (Vec3, Vec3) {
+    let frame = Onb::new(&hit.data.normal);
+    
+    (frame.world_to_local(&-ray.direction.normalized()), frame.world_to_local(&direction.normalized()))
+}
+
+fn albedo_at(texture: &Arc<dyn Texture>, hit: &RayHitResult) -> Color01 {
+    texture.value(hit.data.u, hit.data.v, &hit.data.point)
+}
+
+// Fuzzy reflections are the mirror direction plus a uniform point on a sphere of radius `fuzziness`.
+// The density over directions is the sphere's area density moved to solid angle at every point
+// where the direction pierces that sphere
+fn fuzzy_reflection_pdf(reflection: &Vec3, fuzziness: f64, direction: &Vec3) -> f64 {
+    let dir_norm = direction.normalized();
+    let b = dot(&dir_norm, reflection);
+    let c = reflection.len_sqr() - fuzziness * fuzziness;
+    
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return 0.0;
+    }
+    
+    let disc_sqrt = discriminant.sqrt();
+    let area_pdf = 1.0 / (4.0 * rt_util::PI * fuzziness * fuzziness);
+    
+    let mut pdf = 0.0;
+    for t in [b - disc_sqrt, b + disc_sqrt] {
+        if t <= 0.0 {
+            continue;
+        }
+        
+        let sphere_normal = (dir_norm * t - *reflection) * (1.0 / fuzziness);
+        let cosine = dot(&dir_norm, &sphere_normal).abs();
+        if cosine > 1e-9 {
+            pdf += area_pdf * t * t / cosine;
+        }
+    }
+    
+    pdf
+}
+
 fn schlick_reflectance(cosine: f64, refr_idx: f64) -> f64 {
     let mut r0 = (1.0 - refr_idx) / (1.0 + refr_idx);
     r0 = r0 * r0;
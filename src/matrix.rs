@@ -0,0 +1,147 @@
+use std::ops::Mul;
+
+use crate::rt_util;
+use crate::vector::*;
+
+// Row-major 4x4 matrix for affine transforms, points are column vectors with an implicit w of 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4]
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        
+        Mat4 {m}
+    }
+    
+    pub fn translation(offset: &Vec3) -> Mat4 {
+        let mut matrix = Mat4::identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        
+        matrix
+    }
+    
+    pub fn scaling(factors: &Vec3) -> Mat4 {
+        let mut matrix = Mat4::identity();
+        matrix.m[0][0] = factors.x;
+        matrix.m[1][1] = factors.y;
+        matrix.m[2][2] = factors.z;
+        
+        matrix
+    }
+    
+    // Counter-clockwise rotation around `axis` when looking down at it from its tip (Rodrigues)
+    pub fn rotation(axis: &Vec3, degrees: f64) -> Mat4 {
+        let a = axis.normalized();
+        let (sin, cos) = rt_util::deg_to_rad(degrees).sin_cos();
+        let t = 1.0 - cos;
+        
+        Mat4 {
+            m: [
+                [t * a.x * a.x + cos, t * a.x * a.y - sin * a.z, t * a.x * a.z + sin * a.y, 0.0],
+                [t * a.x * a.y + sin * a.z, t * a.y * a.y + cos, t * a.y * a.z - sin * a.x, 0.0],
+                [t * a.x * a.z - sin * a.y, t * a.y * a.z + sin * a.x, t * a.z * a.z + cos, 0.0],
+                [0.0, 0.0, 0.0, 1.0]
+            ]
+        }
+    }
+    
+    pub fn transposed(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        
+        Mat4 {m}
+    }
+    
+    // Gauss-Jordan elimination with partial pivoting, None when the matrix is singular
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut m = self.m;
+        let mut inverse = Mat4::identity().m;
+        
+        for column in 0..4 {
+            let pivot = (column..4).max_by(|a, b| m[*a][column].abs().total_cmp(&m[*b][column].abs()))?;
+            if m[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            m.swap(column, pivot);
+            inverse.swap(column, pivot);
+            
+            let scale = 1.0 / m[column][column];
+            for j in 0..4 {
+                m[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            
+            for row in 0..4 {
+                let factor = m[row][column];
+                if row == column || factor == 0.0 {
+                    continue;
+                }
+                
+                for j in 0..4 {
+                    m[row][j] -= factor * m[column][j];
+                    inverse[row][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+        
+        Some(Mat4 {m: inverse})
+    }
+    
+    pub fn transform_point(&self, point: &Vec3) -> Vec3 {
+        self.transform_vector(point) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+    
+    // Ignores the translation, for directions and offsets
+    pub fn transform_vector(&self, vector: &Vec3) -> Vec3 {
+        let m = &self.m;
+        
+        Vec3::new(
+            m[0][0] * vector.x + m[0][1] * vector.y + m[0][2] * vector.z,
+            m[1][0] * vector.x + m[1][1] * vector.y + m[1][2] * vector.z,
+            m[2][0] * vector.x + m[2][1] * vector.y + m[2][2] * vector.z
+        )
+    }
+    
+    // Determinant of the upper 3x3 part, the factor the transform scales volumes by
+    pub fn linear_determinant(&self) -> f64 {
+        let m = &self.m;
+        let row_0 = Vec3::new(m[0][0], m[0][1], m[0][2]);
+        let row_1 = Vec3::new(m[1][0], m[1][1], m[1][2]);
+        let row_2 = Vec3::new(m[2][0], m[2][1], m[2][2]);
+        
+        dot(&row_0, &cross(&row_1, &row_2))
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::identity()
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+    
+    fn mul(self, rhs: Mat4) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        
+        Mat4 {m}
+    }
+}
@@ -0,0 +1,354 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::color::Color01;
+use crate::material::*;
+use crate::primitive::*;
+use crate::ray::Ray;
+use crate::rt_util;
+use crate::texture::Texture;
+use crate::vector::*;
+use crate::voxel::VoxelGrid;
+
+// Homogeneous participating medium filling a closed boundary. Hitting it means the ray scattered
+// somewhere inside, at a free-flight distance drawn from the density, so the world traversal picks
+// the closest of medium and surface events and the renderer needs no special volume code
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    // Extinction coefficient, the chance per unit length of a ray scattering
+    density: f64,
+    phase: Arc<dyn Material>
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, phase: Arc<dyn Material>) -> Self {
+        Self {boundary, density, phase}
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        let ray_len = ray.direction.len();
+        // Exponentially distributed, the distance the ray travels through the medium before scattering
+        let mut free_flight = -(1.0 - rt_util::random()).ln() / self.density;
+        let mut scattered_at = None;
+        
+        for_each_segment(self.boundary.as_ref(), ray, t_range, |t_enter, t_exit| {
+            let segment = (t_exit - t_enter) * ray_len;
+            if free_flight < segment {
+                scattered_at = Some(t_enter + free_flight / ray_len);
+                return false;
+            }
+            
+            free_flight -= segment;
+            true
+        });
+        
+        match scattered_at {
+            Some(t) => scattering_event(ray, t, &self.phase),
+            None => RayHitResult::default()
+        }
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+    
+    fn hit_surface(&self, _ray: &Ray, _t_range: Range<f64>) -> RayHitResult {
+        RayHitResult::default()
+    }
+    
+    // Beer-Lambert over the length of the ray inside the boundary
+    fn transmittance(&self, ray: &Ray, t_range: Range<f64>) -> f64 {
+        let mut inside = 0.0;
+        for_each_segment(self.boundary.as_ref(), ray, t_range, |t_enter, t_exit| {
+            inside += t_exit - t_enter;
+            true
+        });
+        
+        (-self.density * inside * ray.direction.len()).exp()
+    }
+}
+
+// Medium whose density varies through a voxel grid stretched over a box. Scattering distances are
+// found by delta tracking against the grid's maximum, the majorant, and shadow rays are attenuated by
+// ratio tracking, so the grid is never stepped through voxel by voxel
+pub struct GridMedium {
+    bounds: Aabb,
+    density: Arc<VoxelGrid>,
+    density_scale: f64,
+    majorant: f64,
+    // The phase function, wrapped in an emissive volume material when the medium glows
+    material: Arc<dyn Material>
+}
+
+impl GridMedium {
+    pub fn new(bounds: Aabb, density: Arc<VoxelGrid>, density_scale: f64, phase: Arc<dyn Material>, emission: Option<VolumeEmission>) -> Self {
+        let material: Arc<dyn Material> = match emission {
+            Some(emission) => Arc::new(EmissiveVolume {phase, emission, bounds}),
+            None => phase
+        };
+        
+        Self {
+            bounds,
+            majorant: density.max_value() * density_scale,
+            density,
+            density_scale,
+            material
+        }
+    }
+    
+    fn density_at(&self, point: &Vec3) -> f64 {
+        self.density.sample(&grid_coords(&self.bounds, point)) * self.density_scale
+    }
+    
+    // Calls `visit` with each tentative collision drawn against the majorant, as the distance along
+    // the ray and the chance it's a real one, until it returns false or the ray leaves the box
+    fn track(&self, ray: &Ray, t_range: Range<f64>, mut visit: impl FnMut(f64, f64) -> bool) {
+        if self.majorant <= 0.0 {
+            return;
+        }
+        
+        let (mut t, t_exit) = match self.bounds.clip(ray, t_range) {
+            Some(clipped) => clipped,
+            None => return
+        };
+        let ray_len = ray.direction.len();
+        
+        loop {
+            t -= (1.0 - rt_util::random()).ln() / (self.majorant * ray_len);
+            if t >= t_exit || !visit(t, self.density_at(&ray.at(t)) / self.majorant) {
+                return;
+            }
+        }
+    }
+}
+
+impl Hittable for GridMedium {
+    // Delta tracking, each tentative collision is real with the chance density / majorant and
+    // otherwise a null collision the ray passes straight through
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        let mut scattered_at = None;
+        self.track(ray, t_range, |t, real_chance| {
+            if rt_util::random() < real_chance {
+                scattered_at = Some(t);
+                return false;
+            }
+            
+            true
+        });
+        
+        match scattered_at {
+            Some(t) => scattering_event(ray, t, &self.material),
+            None => RayHitResult::default()
+        }
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+    
+    fn hit_surface(&self, _ray: &Ray, _t_range: Range<f64>) -> RayHitResult {
+        RayHitResult::default()
+    }
+    
+    // Ratio tracking, the same collisions as delta tracking but each one keeps the null fraction of
+    // the transmittance instead of ending the walk, which is far less noisy
+    fn transmittance(&self, ray: &Ray, t_range: Range<f64>) -> f64 {
+        let mut transmittance = 1.0;
+        self.track(ray, t_range, |_, real_chance| {
+            transmittance *= 1.0 - real_chance.min(1.0);
+            transmittance > 0.0
+        });
+        
+        transmittance
+    }
+}
+
+// Glow of a grid medium, the radiance per voxel is `intensity` times `scale` times either the color or,
+// with a temperature grid in Kelvin, the blackbody color of that temperature
+#[derive(Debug)]
+pub struct VolumeEmission {
+    pub intensity: Arc<VoxelGrid>,
+    pub temperature: Option<Arc<VoxelGrid>>,
+    pub color: Color01,
+    pub scale: f64
+}
+
+impl VolumeEmission {
+    fn radiance(&self, local: &Vec3) -> Color01 {
+        let color = match &self.temperature {
+            Some(temperature) => Color01::blackbody(temperature.sample(local)),
+            None => self.color
+        };
+        
+        color * (self.intensity.sample(local) * self.scale)
+    }
+}
+
+// Phase function of a glowing medium. Every real collision adds the absorbed fraction of the emitted
+// radiance, so a black albedo glows fully and a white one not at all
+#[derive(Debug)]
+struct EmissiveVolume {
+    phase: Arc<dyn Material>,
+    emission: VolumeEmission,
+    bounds: Aabb
+}
+
+impl Material for EmissiveVolume {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        let mut record = self.phase.scatter(ray, hit);
+        let attenuation = record.attenuation;
+        let absorbed = Color01::new(1.0 - attenuation.r.min(1.0), 1.0 - attenuation.g.min(1.0), 1.0 - attenuation.b.min(1.0));
+        record.emission = absorbed * self.emission.radiance(&grid_coords(&self.bounds, &hit.data.point));
+        
+        record
+    }
+    
+    fn scattering_pdf(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> f64 {
+        self.phase.scattering_pdf(ray, hit, direction)
+    }
+    
+    fn eval(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> Color01 {
+        self.phase.eval(ray, hit, direction)
+    }
+}
+
+// Scatters light equally in every direction
+#[derive(Clone, Debug)]
+pub struct Isotropic {
+    pub albedo: Arc<dyn Texture>
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        let direction = rt_util::random_on_unit_sphere();
+        
+        ScatterRecord::diffuse(Ray::new(hit.data.point, direction, ray.time), phase_albedo(&self.albedo, hit), 1.0 / (4.0 * rt_util::PI))
+    }
+    
+    fn scattering_pdf(&self, _ray: &Ray, _hit: &RayHitResult, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * rt_util::PI)
+    }
+    
+    // Phase functions have no cosine term
+    fn eval(&self, _ray: &Ray, hit: &RayHitResult, _direction: &Vec3) -> Color01 {
+        phase_albedo(&self.albedo, hit) * (1.0 / (4.0 * rt_util::PI))
+    }
+}
+
+// Henyey-Greenstein phase function. Positive anisotropy scatters forward like haze and clouds,
+// negative scatters back towards the light, zero is isotropic
+#[derive(Clone, Debug)]
+pub struct HenyeyGreenstein {
+    pub albedo: Arc<dyn Texture>,
+    pub anisotropy: f64
+}
+
+impl HenyeyGreenstein {
+    pub fn new(albedo: Color01, anisotropy: f64) -> HenyeyGreenstein {
+        HenyeyGreenstein {albedo: Arc::new(albedo), anisotropy}
+    }
+    
+    // Density over the sphere of scattering by an angle whose cosine is `cos_theta`
+    fn phase(&self, cos_theta: f64) -> f64 {
+        let g = self.anisotropy;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        
+        (1.0 - g * g) / (4.0 * rt_util::PI * denom * denom.sqrt())
+    }
+    
+    fn cos_theta(ray: &Ray, direction: &Vec3) -> f64 {
+        dot(&ray.direction.normalized(), &direction.normalized())
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        let g = self.anisotropy;
+        let xi = rt_util::random();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * xi
+        }
+        else {
+            let ratio = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+            ((1.0 + g * g - ratio * ratio) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * rt_util::PI * rt_util::random();
+        
+        // Angles are measured from the direction the ray was already travelling in
+        let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let direction = Onb::new(&ray.direction).local_to_world(&local);
+        
+        ScatterRecord::diffuse(Ray::new(hit.data.point, direction, ray.time), phase_albedo(&self.albedo, hit), self.phase(cos_theta))
+    }
+    
+    fn scattering_pdf(&self, ray: &Ray, _hit: &RayHitResult, direction: &Vec3) -> f64 {
+        self.phase(HenyeyGreenstein::cos_theta(ray, direction))
+    }
+    
+    fn eval(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> Color01 {
+        phase_albedo(&self.albedo, hit) * self.phase(HenyeyGreenstein::cos_theta(ray, direction))
+    }
+}
+
+// Calls `visit` with the start and end of each stretch of the ray inside a closed boundary, clipped to
+// `t_range`, until it returns false. Handles non-convex shapes and rays that start inside
+fn for_each_segment(boundary: &dyn Hittable, ray: &Ray, t_range: Range<f64>, mut visit: impl FnMut(f64, f64) -> bool) {
+    let mut t = t_range.start;
+    
+    while t < t_range.end {
+        let crossing = boundary.hit(ray, t..rt_util::INFINITY);
+        if !crossing.is_hit {
+            return;
+        }
+        
+        let (t_enter, t_exit) = if crossing.data.front_face {
+            let exit = boundary.hit(ray, crossing.data.ray_t + 1e-4..rt_util::INFINITY);
+            if !exit.is_hit {
+                return;
+            }
+            
+            (crossing.data.ray_t, exit.data.ray_t)
+        }
+        else {
+            (t, crossing.data.ray_t)
+        };
+        
+        if t_enter >= t_range.end || !visit(t_enter, t_exit.min(t_range.end)) {
+            return;
+        }
+        
+        t = t_exit + 1e-4;
+    }
+}
+
+fn scattering_event(ray: &Ray, t: f64, material: &Arc<dyn Material>) -> RayHitResult {
+    RayHitResult {
+        is_hit: true,
+        data: RayHitData {
+            point: ray.at(t),
+            // Phase functions only look at the ray direction, the normal is arbitrary
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            ray_t: t,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            material: Some(material.clone())
+        }
+    }
+}
+
+// Position inside a box mapped to the unit cube a voxel grid covers
+fn grid_coords(bounds: &Aabb, point: &Vec3) -> Vec3 {
+    let extent = bounds.max - bounds.min;
+    let offset = *point - bounds.min;
+    
+    Vec3::new(offset.x / extent.x, offset.y / extent.y, offset.z / extent.z)
+}
+
+fn phase_albedo(albedo: &Arc<dyn Texture>, hit: &RayHitResult) -> Color01 {
+    albedo.value(hit.data.u, hit.data.v, &hit.data.point)
+}
@@ -0,0 +1,134 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::material::Material;
+use crate::primitive::*;
+use crate::ray::Ray;
+use crate::vector::*;
+
+// Each corner of a face indexes the shared arrays separately, like OBJ does
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshFace {
+    pub positions: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub faces: Vec<MeshFace>
+}
+
+pub struct TriangleMesh {
+    data: Arc<MeshData>,
+    material: Arc<dyn Material>,
+    bvh: BvhNode
+}
+
+impl TriangleMesh {
+    pub fn new(data: MeshData, material: Arc<dyn Material>) -> Self {
+        let data = Arc::new(data);
+        
+        let mut triangles = HittableList::new();
+        for face in 0..data.faces.len() {
+            triangles.add(Box::new(MeshTriangle {mesh: data.clone(), face, material: material.clone()}));
+        }
+        
+        Self {
+            data,
+            material,
+            bvh: BvhNode::new(triangles)
+        }
+    }
+    
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.material
+    }
+    
+    // Flat copies of every face, enough to sample the mesh when it is used as a light
+    pub fn triangles(&self) -> Vec<Triangle> {
+        self.data.faces.iter()
+            .map(|face| Triangle {
+                vertices: face.positions.map(|index| self.data.positions[index]),
+                material: self.material.clone()
+            })
+            .collect()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        self.bvh.hit(ray, t_range)
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
+    }
+}
+
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    face: usize,
+    material: Arc<dyn Material>
+}
+
+impl MeshTriangle {
+    fn vertices(&self) -> [Vec3; 3] {
+        let indices = self.mesh.faces[self.face].positions;
+        
+        [self.mesh.positions[indices[0]], self.mesh.positions[indices[1]], self.mesh.positions[indices[2]]]
+    }
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        let mut result = RayHitResult::default();
+        
+        let vertices = self.vertices();
+        let (t, barycentric) = match intersect_triangle(ray, &vertices, t_range) {
+            Some(intersection) => intersection,
+            None => return result
+        };
+        
+        let face = &self.mesh.faces[self.face];
+        
+        result.is_hit = true;
+        result.data.ray_t = t;
+        result.data.point = ray.at(t);
+        result.data.material = Some(self.material.clone());
+        
+        match face.uvs {
+            Some(uv_indices) => {
+                let uvs = uv_indices.map(|index| self.mesh.uvs[index]);
+                result.data.u = barycentric[0] * uvs[0].0 + barycentric[1] * uvs[1].0 + barycentric[2] * uvs[2].0;
+                result.data.v = barycentric[0] * uvs[0].1 + barycentric[1] * uvs[1].1 + barycentric[2] * uvs[2].1;
+            },
+            None => {
+                result.data.u = barycentric[1];
+                result.data.v = barycentric[2];
+            }
+        }
+        
+        // The face side comes from the geometric normal, the interpolated normal only shades
+        let geometric_normal = cross(&(vertices[1] - vertices[0]), &(vertices[2] - vertices[0])).normalized();
+        result.data.set_face_normal(ray, &geometric_normal);
+        
+        if let Some(normal_indices) = face.normals {
+            let normals = normal_indices.map(|index| self.mesh.normals[index]);
+            let shading_normal = (barycentric[0] * normals[0] + barycentric[1] * normals[1] + barycentric[2] * normals[2]).normalized();
+            
+            result.data.normal = if result.data.front_face { shading_normal } else { -shading_normal };
+        }
+        
+        result
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        triangle_bounding_box(&self.vertices())
+    }
+}
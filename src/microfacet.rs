@@ -0,0 +1,388 @@
+use crate::color::Color01;
+use crate::rt_util;
+use crate::vector::*;
+
+// Isotropic GGX (Trowbridge-Reitz) distribution. All directions are in the local shading frame
+// where the macro surface normal is +z
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub alpha: f64
+}
+
+impl Ggx {
+    // Roughness is squared into alpha so that it changes the look roughly linearly
+    pub fn from_roughness(roughness: f64) -> Ggx {
+        Ggx {alpha: (roughness * roughness).max(1e-4)}
+    }
+    
+    // Below this the lobe is narrower than anything sampling can resolve and is treated as a mirror
+    pub fn is_smooth(&self) -> bool {
+        self.alpha <= 1e-3
+    }
+    
+    pub fn d(&self, m: &Vec3) -> f64 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        
+        let alpha_sqr = self.alpha * self.alpha;
+        let cos_sqr = m.z * m.z;
+        let denom = cos_sqr * (alpha_sqr - 1.0) + 1.0;
+        
+        alpha_sqr / (rt_util::PI * denom * denom)
+    }
+    
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos_sqr = w.z * w.z;
+        if cos_sqr <= 0.0 {
+            return f64::INFINITY;
+        }
+        
+        let tan_sqr = (1.0 - cos_sqr).max(0.0) / cos_sqr;
+        
+        0.5 * (-1.0 + (1.0 + self.alpha * self.alpha * tan_sqr).sqrt())
+    }
+    
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+    
+    // Height-correlated masking-shadowing
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+    
+    // Density of sample_visible_normal returning `m` for the view direction `wo`
+    pub fn visible_normal_pdf(&self, wo: &Vec3, m: &Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        
+        self.g1(wo) * dot(wo, m).max(0.0) * self.d(m) / wo.z
+    }
+    
+    // Samples only the microfacet normals visible from `wo` (Heitz 2018), which keeps the sample
+    // weights close to one even at grazing angles
+    pub fn sample_visible_normal(&self, wo: &Vec3) -> Vec3 {
+        let view = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).normalized();
+        
+        let len_sqr = view.x * view.x + view.y * view.y;
+        let t1 = if len_sqr > 0.0 {
+            Vec3::new(-view.y, view.x, 0.0) * (1.0 / len_sqr.sqrt())
+        }
+        else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(&view, &t1);
+        
+        let radius = rt_util::random().sqrt();
+        let phi = 2.0 * rt_util::PI * rt_util::random();
+        let p1 = radius * phi.cos();
+        let s = 0.5 * (1.0 + view.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * radius * phi.sin();
+        let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        
+        let normal = p1 * t1 + p2 * t2 + p3 * view;
+        
+        Vec3::new(self.alpha * normal.x, self.alpha * normal.y, normal.z.max(0.0)).normalized()
+    }
+    
+    // Rough dielectric interface (Walter et al. 2007). `eta` is the index on the far side over the
+    // index on the side of `wo`, directions with negative z are transmitted
+    pub fn sample_dielectric(&self, wo: &Vec3, eta: f64) -> Option<Vec3> {
+        let m = self.sample_visible_normal(wo);
+        let wi = sample_dielectric_direction(wo, &m, eta);
+        
+        // Reflections off steep microfacets can point into the surface and refractions out of it,
+        // the pdf would read those as the other lobe so they are dropped
+        if (wi.z > 0.0) != (dot(&wi, &m) > 0.0) {
+            return None;
+        }
+        
+        Some(wi)
+    }
+    
+    pub fn dielectric_pdf(&self, wo: &Vec3, wi: &Vec3, eta: f64) -> f64 {
+        let m = match dielectric_half_vector(wo, wi, eta) {
+            Some(m) => m,
+            None => return 0.0
+        };
+        
+        let cos_om = dot(wo, &m);
+        let fresnel = fresnel_dielectric(cos_om, eta);
+        let normal_pdf = self.visible_normal_pdf(wo, &m);
+        
+        if wi.z > 0.0 {
+            fresnel * normal_pdf / (4.0 * cos_om)
+        }
+        else {
+            // Jacobian of the refracted direction with respect to the microfacet normal
+            let cos_im = dot(wi, &m);
+            let denom = cos_om + eta * cos_im;
+            
+            (1.0 - fresnel) * normal_pdf * eta * eta * cos_im.abs() / (denom * denom)
+        }
+    }
+    
+    // BSDF times the cosine of `wi`
+    pub fn dielectric_eval(&self, wo: &Vec3, wi: &Vec3, eta: f64) -> f64 {
+        let m = match dielectric_half_vector(wo, wi, eta) {
+            Some(m) => m,
+            None => return 0.0
+        };
+        
+        let cos_om = dot(wo, &m);
+        let fresnel = fresnel_dielectric(cos_om, eta);
+        let dg = self.d(&m) * self.g2(wo, wi);
+        
+        if wi.z > 0.0 {
+            fresnel * dg / (4.0 * wo.z)
+        }
+        else {
+            let cos_im = dot(wi, &m);
+            let denom = cos_om + eta * cos_im;
+            
+            (1.0 - fresnel) * dg * cos_om * eta * eta * cos_im.abs() / (wo.z * denom * denom)
+        }
+    }
+}
+
+// Reflects or refracts `wo` through the microfacet `m`, picking between the two by the Fresnel term
+pub fn sample_dielectric_direction(wo: &Vec3, m: &Vec3, eta: f64) -> Vec3 {
+    if rt_util::random() < fresnel_dielectric(dot(wo, m), eta) {
+        (-*wo).reflect(m)
+    }
+    else {
+        (-*wo).refract(m, 1.0 / eta).normalized()
+    }
+}
+
+// Microfacet normal that turns wo into wi, None when no microfacet can
+fn dielectric_half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<Vec3> {
+    let is_reflection = wi.z > 0.0;
+    let mut m = if is_reflection {
+        (*wo + *wi).normalized()
+    }
+    else {
+        (*wo + *wi * eta).normalized()
+    };
+    if m.z < 0.0 {
+        m = -m;
+    }
+    
+    // Both directions have to see the same side of the microfacet they use
+    if dot(wo, &m) <= 0.0 || (dot(wi, &m) > 0.0) != is_reflection {
+        return None;
+    }
+    
+    Some(m)
+}
+
+// Generalized Trowbridge-Reitz with gamma = 1, the long tailed distribution of the Disney clearcoat
+#[derive(Clone, Copy, Debug)]
+pub struct Gtr1 {
+    pub alpha: f64
+}
+
+impl Gtr1 {
+    pub fn d(&self, m: &Vec3) -> f64 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        
+        let alpha_sqr = self.alpha * self.alpha;
+        let t = 1.0 + (alpha_sqr - 1.0) * m.z * m.z;
+        
+        (alpha_sqr - 1.0) / (rt_util::PI * alpha_sqr.ln() * t)
+    }
+    
+    // Samples microfacet normals proportionally to D(m) * cos(m)
+    pub fn sample_normal(&self) -> Vec3 {
+        let alpha_sqr = self.alpha * self.alpha;
+        let cos_theta = ((1.0 - alpha_sqr.powf(1.0 - rt_util::random())) / (1.0 - alpha_sqr)).max(0.0).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * rt_util::PI * rt_util::random();
+        
+        Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+    
+    pub fn normal_pdf(&self, m: &Vec3) -> f64 {
+        self.d(m) * m.z
+    }
+}
+
+// Schlick's approximation of the Fresnel term for a reflectance of f0 at normal incidence
+pub fn fresnel_schlick(cos_theta: f64, f0: Color01) -> Color01 {
+    let weight = schlick_weight(cos_theta);
+    
+    f0 * (1.0 - weight) + Color01::new(weight, weight, weight)
+}
+
+pub fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+// Unpolarized Fresnel reflectance of a conductor with complex index of refraction eta + ik, per channel
+pub fn fresnel_conductor(cos_theta: f64, eta: &Color01, k: &Color01) -> Color01 {
+    Color01::new(
+        fresnel_conductor_channel(cos_theta, eta.r, k.r),
+        fresnel_conductor_channel(cos_theta, eta.g, k.g),
+        fresnel_conductor_channel(cos_theta, eta.b, k.b)
+    )
+}
+
+fn fresnel_conductor_channel(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos_sqr = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin_sqr = 1.0 - cos_sqr;
+    
+    let t0 = eta * eta - k * k - sin_sqr;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    
+    let t1 = a2_plus_b2 + cos_sqr;
+    let t2 = 2.0 * cos_sqr.sqrt() * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    
+    let t3 = cos_sqr * a2_plus_b2 + sin_sqr * sin_sqr;
+    let t4 = t2 * sin_sqr;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    
+    0.5 * (rs + rp)
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface, eta is the ratio of the index on the
+// far side over the index on the side of the incoming direction
+pub fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let cos_i = cos_theta.clamp(0.0, 1.0);
+    let sin_t_sqr = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin_t_sqr >= 1.0 {
+        return 1.0;
+    }
+    
+    let cos_t = (1.0 - sin_t_sqr).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    
+    0.5 * (rs * rs + rp * rp)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    
+    // Histogram bins over the sphere, uniform in theta and phi
+    const THETA_BINS: usize = 24;
+    const PHI_BINS: usize = 48;
+    // Midpoint steps per bin and axis when integrating a pdf over one bin
+    const BIN_STEPS: usize = 16;
+    const SAMPLES: usize = 400_000;
+    
+    fn bin_index(w: &Vec3) -> usize {
+        let theta = w.z.clamp(-1.0, 1.0).acos();
+        let phi = w.y.atan2(w.x) + rt_util::PI;
+        let i = ((theta / rt_util::PI * THETA_BINS as f64) as usize).min(THETA_BINS - 1);
+        let j = ((phi / (2.0 * rt_util::PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+        
+        i * PHI_BINS + j
+    }
+    
+    // Probability of every bin under `pdf`, each midpoint weighted by the solid angle it covers
+    fn bin_probabilities(pdf: &impl Fn(&Vec3) -> f64) -> Vec<f64> {
+        let theta_step = rt_util::PI / (THETA_BINS * BIN_STEPS) as f64;
+        let phi_step = 2.0 * rt_util::PI / (PHI_BINS * BIN_STEPS) as f64;
+        let mut probabilities = vec![0.0; THETA_BINS * PHI_BINS];
+        
+        for i in 0..THETA_BINS * BIN_STEPS {
+            let theta = (i as f64 + 0.5) * theta_step;
+            let solid_angle = theta.sin() * theta_step * phi_step;
+            for j in 0..PHI_BINS * BIN_STEPS {
+                let phi = (j as f64 + 0.5) * phi_step - rt_util::PI;
+                let w = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                probabilities[(i / BIN_STEPS) * PHI_BINS + j / BIN_STEPS] += pdf(&w) * solid_angle;
+            }
+        }
+        
+        probabilities
+    }
+    
+    pub(crate) fn integrate_over_sphere(pdf: impl Fn(&Vec3) -> f64) -> f64 {
+        bin_probabilities(&pdf).iter().sum()
+    }
+    
+    // Bins directions drawn from `sample` and checks every bin against the share `pdf` predicts,
+    // within five standard deviations plus a little room for the integration error. Dropped
+    // samples fall in no bin, which is where a pdf leaves its missing mass
+    pub(crate) fn assert_samples_match_pdf(seed: u64, mut sample: impl FnMut() -> Option<Vec3>, pdf: impl Fn(&Vec3) -> f64) {
+        rt_util::seed_rng(seed);
+        let mut counts = vec![0usize; THETA_BINS * PHI_BINS];
+        for _ in 0..SAMPLES {
+            if let Some(w) = sample() {
+                counts[bin_index(&w)] += 1;
+            }
+        }
+        
+        for (bin, (count, probability)) in counts.iter().zip(bin_probabilities(&pdf)).enumerate() {
+            let expected = probability * SAMPLES as f64;
+            let tolerance = 5.0 * expected.max(1.0).sqrt() + 1e-4 * SAMPLES as f64;
+            assert!((*count as f64 - expected).abs() < tolerance,
+                "bin {} (theta {}, phi {}) has {} samples, expected {:.1}", bin, bin / PHI_BINS, bin % PHI_BINS, count, expected);
+        }
+    }
+    
+    fn view_direction(cos_theta: f64) -> Vec3 {
+        Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta)
+    }
+    
+    #[test]
+    fn visible_normals_match_their_pdf() {
+        for (alpha, cos_theta) in [(0.1, 1.0), (0.3, 0.7), (0.6, 0.2)] {
+            let distribution = Ggx {alpha};
+            let wo = view_direction(cos_theta);
+            
+            assert!((integrate_over_sphere(|m| distribution.visible_normal_pdf(&wo, m)) - 1.0).abs() < 1e-3);
+            assert_samples_match_pdf(1, || Some(distribution.sample_visible_normal(&wo)), |m| distribution.visible_normal_pdf(&wo, m));
+        }
+    }
+    
+    #[test]
+    fn dielectric_pdf_integrates_to_one() {
+        rt_util::seed_rng(4);
+        for eta in [1.5, 1.0 / 1.5, 2.4] {
+            for alpha in [0.1, 0.3, 0.6] {
+                let distribution = Ggx {alpha};
+                for cos_theta in [0.9, 0.4] {
+                    let wo = view_direction(cos_theta);
+                    let integral = integrate_over_sphere(|wi| distribution.dielectric_pdf(&wo, wi, eta));
+                    
+                    // Only the samples that leave on the wrong side of the surface are missing
+                    let steps = 100_000;
+                    let kept = (0..steps).filter(|_| distribution.sample_dielectric(&wo, eta).is_some()).count() as f64 / steps as f64;
+                    assert!((integral - kept).abs() < 0.01, "eta {}, alpha {}, cos {}: {} against {}", eta, alpha, cos_theta, integral, kept);
+                    if alpha <= 0.1 {
+                        assert!((integral - 1.0).abs() < 0.03, "eta {}, alpha {}, cos {}: {}", eta, alpha, cos_theta, integral);
+                    }
+                }
+            }
+        }
+    }
+    
+    #[test]
+    fn dielectric_samples_match_their_pdf() {
+        for (eta, alpha, cos_theta) in [(1.5, 0.2, 0.8), (1.0 / 1.5, 0.4, 0.6), (2.4, 0.6, 0.3)] {
+            let distribution = Ggx {alpha};
+            let wo = view_direction(cos_theta);
+            
+            assert_samples_match_pdf(2, || distribution.sample_dielectric(&wo, eta), |wi| distribution.dielectric_pdf(&wo, wi, eta));
+        }
+    }
+    
+    #[test]
+    fn clearcoat_normals_match_their_pdf() {
+        for alpha in [0.1, 0.5] {
+            let distribution = Gtr1 {alpha};
+            
+            assert!((integrate_over_sphere(|m| distribution.normal_pdf(m)) - 1.0).abs() < 1e-3);
+            assert_samples_match_pdf(3, || Some(distribution.sample_normal()), |m| distribution.normal_pdf(m));
+        }
+    }
+}
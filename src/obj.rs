@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::color::Color01;
+use crate::material::*;
+use crate::mesh::*;
+use crate::vector::Vec3;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        error: io::Error
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io {path, error} => write!(f, "{}: {}", path.display(), error),
+            ObjError::Parse {path, line, message} => write!(f, "{}:{}: {}", path.display(), line, message)
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+// Loads every group/material combination of an OBJ file as its own TriangleMesh. Faces that come
+// before any usemtl statement get the default material
+pub fn load_obj(path: &Path, default_material: Arc<dyn Material>) -> Result<Vec<TriangleMesh>, ObjError> {
+    let source = read_file(path)?;
+    let mut parser = ObjParser::new(path, default_material);
+    
+    for (line_idx, line) in source.lines().enumerate() {
+        parser.parse_line(line_idx + 1, line)?;
+    }
+    
+    Ok(parser.finish())
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let source = read_file(path)?;
+    
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut current: Option<(String, MtlEntry)> = None;
+    
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_num = line_idx + 1;
+        let mut tokens = Tokens::new(path, line_num, line);
+        
+        let keyword = match tokens.token() {
+            Some(keyword) => keyword,
+            None => continue
+        };
+        
+        if keyword == "newmtl" {
+            if let Some((name, entry)) = current.take() {
+                materials.insert(name, entry.to_material());
+            }
+            
+            current = Some((tokens.rest("a material name")?, MtlEntry::default()));
+            continue;
+        }
+        
+        let entry = match current.as_mut() {
+            Some((_, entry)) => entry,
+            None if is_mtl_property(keyword) => return Err(tokens.error(format!("'{}' appears before any newmtl statement", keyword))),
+            None => continue
+        };
+        
+        match keyword {
+            "Kd" => entry.diffuse = tokens.color()?,
+            "Ks" => entry.specular = tokens.color()?,
+            "Ke" => entry.emission = tokens.color()?,
+            "Ns" => entry.shininess = Some(tokens.number("a specular exponent")?),
+            "Ni" => entry.refraction_idx = Some(tokens.number("an index of refraction")?),
+            "d" => entry.dissolve = tokens.number("a dissolve factor")?,
+            "Tr" => entry.dissolve = 1.0 - tokens.number("a transparency factor")?,
+            "illum" => entry.illum = tokens.integer("an illumination model")?,
+            _ => continue
+        }
+        
+        tokens.expect_end()?;
+    }
+    
+    if let Some((name, entry)) = current.take() {
+        materials.insert(name, entry.to_material());
+    }
+    
+    Ok(materials)
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|error| ObjError::Io {path: path.to_path_buf(), error})
+}
+
+fn is_mtl_property(keyword: &str) -> bool {
+    matches!(keyword, "Kd" | "Ks" | "Ke" | "Ns" | "Ni" | "d" | "Tr" | "illum")
+}
+
+struct MtlEntry {
+    diffuse: Color01,
+    specular: Color01,
+    emission: Color01,
+    shininess: Option<f64>,
+    refraction_idx: Option<f64>,
+    dissolve: f64,
+    illum: i64
+}
+
+impl Default for MtlEntry {
+    fn default() -> Self {
+        MtlEntry {
+            diffuse: Color01::new(0.8, 0.8, 0.8),
+            specular: Color01::default(),
+            emission: Color01::default(),
+            shininess: None,
+            refraction_idx: None,
+            dissolve: 1.0,
+            illum: 2
+        }
+    }
+}
+
+impl MtlEntry {
+    // Emissive entries become lights, transparent models map to Dielectric, the "reflection on"
+    // models map to Metal and everything else is treated as a diffuse Lambert surface
+    fn to_material(&self) -> Arc<dyn Material> {
+        let refraction_idx = self.refraction_idx.unwrap_or(1.5);
+        
+        if self.emission.r > 0.0 || self.emission.g > 0.0 || self.emission.b > 0.0 {
+            return Arc::new(DiffuseLight::new(self.emission, 1.0));
+        }
+        
+        if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            return Arc::new(Dielectric::new(refraction_idx));
+        }
+        
+        if matches!(self.illum, 3 | 5 | 8) {
+            let has_specular = self.specular.r > 0.0 || self.specular.g > 0.0 || self.specular.b > 0.0;
+            let albedo = if has_specular { self.specular } else { self.diffuse };
+            // Phong exponent to a roughness-like perturbation, high exponents give sharp mirrors
+            let fuzziness = self.shininess.map_or(0.0, |ns| (2.0 / (ns.max(0.0) + 2.0)).sqrt());
+            
+            return Arc::new(Metal::new(albedo, fuzziness));
+        }
+        
+        Arc::new(Lambertian::new(self.diffuse))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FaceVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>
+}
+
+// Faces sharing a group and material, each bucket becomes one mesh
+type FaceBucket = (Arc<dyn Material>, Vec<[FaceVertex; 3]>);
+
+struct ObjParser<'a> {
+    path: &'a Path,
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    current_group: String,
+    current_material: Arc<dyn Material>,
+    buckets: Vec<FaceBucket>,
+    bucket_lookup: HashMap<String, usize>,
+    current_material_name: String
+}
+
+impl<'a> ObjParser<'a> {
+    fn new(path: &'a Path, default_material: Arc<dyn Material>) -> Self {
+        ObjParser {
+            path,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            materials: HashMap::new(),
+            current_group: String::from("default"),
+            current_material: default_material,
+            buckets: Vec::new(),
+            bucket_lookup: HashMap::new(),
+            current_material_name: String::new()
+        }
+    }
+    
+    fn parse_line(&mut self, line_num: usize, line: &str) -> Result<(), ObjError> {
+        let mut tokens = Tokens::new(self.path, line_num, line);
+        
+        let keyword = match tokens.token() {
+            Some(keyword) => keyword,
+            None => return Ok(())
+        };
+        
+        match keyword {
+            "v" => {
+                let position = tokens.vector()?;
+                // An optional homogeneous w component is allowed but ignored
+                tokens.optional_number()?;
+                self.positions.push(position);
+            },
+            "vn" => {
+                let normal = tokens.vector()?;
+                if normal.len_sqr() == 0.0 {
+                    return Err(tokens.error(String::from("normal has zero length")));
+                }
+                self.normals.push(normal.normalized());
+            },
+            "vt" => {
+                let u = tokens.number("a texture coordinate")?;
+                let v = tokens.optional_number()?.unwrap_or(0.0);
+                tokens.optional_number()?;
+                self.uvs.push((u, v));
+            },
+            "f" => return self.parse_face(&mut tokens),
+            "g" | "o" => {
+                self.current_group = tokens.rest("a group name").unwrap_or_else(|_| String::from("default"));
+                return Ok(());
+            },
+            "usemtl" => {
+                let name = tokens.rest("a material name")?;
+                self.current_material = match self.materials.get(&name) {
+                    Some(material) => material.clone(),
+                    None => return Err(tokens.error(format!("unknown material '{}'", name)))
+                };
+                self.current_material_name = name;
+                return Ok(());
+            },
+            "mtllib" => {
+                let file_names = tokens.rest("a material library file")?;
+                let base_dir = self.path.parent().unwrap_or(Path::new(""));
+                
+                for file_name in file_names.split_whitespace() {
+                    self.materials.extend(load_mtl(&base_dir.join(file_name))?);
+                }
+                return Ok(());
+            },
+            // Smoothing groups, lines, points and free-form geometry don't affect triangle meshes
+            _ => return Ok(())
+        }
+        
+        tokens.expect_end()
+    }
+    
+    fn parse_face(&mut self, tokens: &mut Tokens) -> Result<(), ObjError> {
+        let mut corners: Vec<FaceVertex> = Vec::new();
+        
+        while let Some(token) = tokens.token() {
+            corners.push(self.parse_face_vertex(tokens, token)?);
+        }
+        
+        if corners.len() < 3 {
+            return Err(tokens.error(format!("face needs at least 3 vertices, found {}", corners.len())));
+        }
+        
+        let has_uvs = corners[0].uv.is_some();
+        let has_normals = corners[0].normal.is_some();
+        if corners.iter().any(|corner| corner.uv.is_some() != has_uvs || corner.normal.is_some() != has_normals) {
+            return Err(tokens.error(String::from("face vertices mix different v/vt/vn layouts")));
+        }
+        
+        let key = format!("{}\u{0}{}", self.current_group, self.current_material_name);
+        let bucket_idx = match self.bucket_lookup.get(&key) {
+            Some(idx) => *idx,
+            None => {
+                self.buckets.push((self.current_material.clone(), Vec::new()));
+                self.bucket_lookup.insert(key, self.buckets.len() - 1);
+                self.buckets.len() - 1
+            }
+        };
+        
+        // Polygons are fan triangulated around their first vertex
+        for i in 1..corners.len() - 1 {
+            self.buckets[bucket_idx].1.push([corners[0], corners[i], corners[i + 1]]);
+        }
+        
+        Ok(())
+    }
+    
+    fn parse_face_vertex(&self, tokens: &Tokens, token: &str) -> Result<FaceVertex, ObjError> {
+        let mut parts = token.split('/');
+        
+        let position = self.resolve_index(tokens, parts.next(), self.positions.len(), "vertex")?;
+        let uv = match parts.next() {
+            Some("") | None => None,
+            Some(part) => Some(self.resolve_index(tokens, Some(part), self.uvs.len(), "texture coordinate")?)
+        };
+        let normal = match parts.next() {
+            Some("") | None => None,
+            Some(part) => Some(self.resolve_index(tokens, Some(part), self.normals.len(), "normal")?)
+        };
+        
+        if parts.next().is_some() {
+            return Err(tokens.error(format!("malformed face vertex '{}'", token)));
+        }
+        
+        Ok(FaceVertex {position, uv, normal})
+    }
+    
+    // OBJ indices are 1-based, negative indices count back from the most recent element
+    fn resolve_index(&self, tokens: &Tokens, part: Option<&str>, count: usize, kind: &str) -> Result<usize, ObjError> {
+        let part = part.unwrap_or("");
+        let index: i64 = part.parse().map_err(|_| tokens.error(format!("invalid {} index '{}'", kind, part)))?;
+        
+        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(tokens.error(format!("{} index {} is out of range ({} defined so far)", kind, index, count)));
+        }
+        
+        Ok(resolved as usize)
+    }
+    
+    fn finish(self) -> Vec<TriangleMesh> {
+        self.buckets.iter()
+            .map(|(material, triangles)| TriangleMesh::new(self.build_mesh_data(triangles), material.clone()))
+            .collect()
+    }
+    
+    // Copies only the attributes a bucket references so every mesh gets compact arrays
+    fn build_mesh_data(&self, triangles: &[[FaceVertex; 3]]) -> MeshData {
+        let mut data = MeshData::default();
+        let mut position_map: HashMap<usize, usize> = HashMap::new();
+        let mut normal_map: HashMap<usize, usize> = HashMap::new();
+        let mut uv_map: HashMap<usize, usize> = HashMap::new();
+        
+        for triangle in triangles {
+            let positions = triangle.map(|corner| remap(&mut position_map, &mut data.positions, &self.positions, corner.position));
+            
+            let normals = if triangle.iter().all(|corner| corner.normal.is_some()) {
+                Some(triangle.map(|corner| remap(&mut normal_map, &mut data.normals, &self.normals, corner.normal.unwrap())))
+            }
+            else {
+                None
+            };
+            
+            let uvs = if triangle.iter().all(|corner| corner.uv.is_some()) {
+                Some(triangle.map(|corner| remap(&mut uv_map, &mut data.uvs, &self.uvs, corner.uv.unwrap())))
+            }
+            else {
+                None
+            };
+            
+            data.faces.push(MeshFace {positions, normals, uvs});
+        }
+        
+        data
+    }
+}
+
+fn remap<T: Copy>(map: &mut HashMap<usize, usize>, target: &mut Vec<T>, source: &[T], index: usize) -> usize {
+    *map.entry(index).or_insert_with(|| {
+        target.push(source[index]);
+        target.len() - 1
+    })
+}
+
+// Whitespace tokenizer over a single line that knows where it is for error reporting
+struct Tokens<'a> {
+    path: &'a Path,
+    line_num: usize,
+    inner: std::str::SplitWhitespace<'a>
+}
+
+impl<'a> Tokens<'a> {
+    fn new(path: &'a Path, line_num: usize, line: &'a str) -> Self {
+        let line = match line.find('#') {
+            Some(comment_start) => &line[..comment_start],
+            None => line
+        };
+        
+        Tokens {
+            path,
+            line_num,
+            inner: line.split_whitespace()
+        }
+    }
+    
+    fn token(&mut self) -> Option<&'a str> {
+        self.inner.next()
+    }
+    
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse {path: self.path.to_path_buf(), line: self.line_num, message}
+    }
+    
+    fn number(&mut self, what: &str) -> Result<f64, ObjError> {
+        match self.inner.next() {
+            Some(token) => token.parse().map_err(|_| self.error(format!("expected {}, found '{}'", what, token))),
+            None => Err(self.error(format!("expected {}, found end of line", what)))
+        }
+    }
+    
+    fn optional_number(&mut self) -> Result<Option<f64>, ObjError> {
+        match self.inner.next() {
+            Some(token) => token.parse().map(Some).map_err(|_| self.error(format!("expected a number, found '{}'", token))),
+            None => Ok(None)
+        }
+    }
+    
+    fn integer(&mut self, what: &str) -> Result<i64, ObjError> {
+        match self.inner.next() {
+            Some(token) => token.parse().map_err(|_| self.error(format!("expected {}, found '{}'", what, token))),
+            None => Err(self.error(format!("expected {}, found end of line", what)))
+        }
+    }
+    
+    fn vector(&mut self) -> Result<Vec3, ObjError> {
+        Ok(Vec3::new(self.number("a x coordinate")?, self.number("a y coordinate")?, self.number("a z coordinate")?))
+    }
+    
+    fn color(&mut self) -> Result<Color01, ObjError> {
+        let r = self.number("a red component")?;
+        // A single value sets all three channels
+        match self.optional_number()? {
+            Some(g) => Ok(Color01::new(r, g, self.number("a blue component")?)),
+            None => Ok(Color01::new(r, r, r))
+        }
+    }
+    
+    fn rest(&mut self, what: &str) -> Result<String, ObjError> {
+        let rest: Vec<&str> = self.inner.by_ref().collect();
+        if rest.is_empty() {
+            return Err(self.error(format!("expected {}", what)));
+        }
+        
+        Ok(rest.join(" "))
+    }
+    
+    fn expect_end(&mut self) -> Result<(), ObjError> {
+        match self.inner.next() {
+            Some(token) => Err(self.error(format!("unexpected trailing value '{}'", token))),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn parse(lines: &[&str]) -> Result<ObjParser<'static>, ObjError> {
+        let mut parser = ObjParser::new(Path::new("test.obj"), Arc::new(Lambertian::new(Color01::default())));
+        for (line_idx, line) in lines.iter().enumerate() {
+            parser.parse_line(line_idx + 1, line)?;
+        }
+        
+        Ok(parser)
+    }
+    
+    fn error_line(result: Result<ObjParser, ObjError>) -> (usize, String) {
+        match result {
+            Err(ObjError::Parse {line, message, ..}) => (line, message),
+            Err(error) => panic!("expected a parse error, got {}", error),
+            Ok(_) => panic!("expected a parse error")
+        }
+    }
+    
+    #[test]
+    fn negative_indices_count_back_from_the_last_element() {
+        let parser = parse(&[
+            "v 0 0 0", "v 1 0 0", "v 0 1 0",
+            "vn 0 0 1",
+            "vt 0 0", "vt 1 0", "vt 0 1",
+            "f -3/-3/-1 -2/-2/-1 -1/-1/-1"
+        ]).unwrap();
+        
+        let corners = parser.buckets[0].1[0];
+        assert_eq!(corners.map(|corner| corner.position), [0, 1, 2]);
+        assert_eq!(corners.map(|corner| corner.uv), [Some(0), Some(1), Some(2)]);
+        assert_eq!(corners.map(|corner| corner.normal), [Some(0); 3]);
+    }
+    
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let parser = parse(&["v 0 0 0", "v 1 0 0", "v 1 1 0", "v 0.5 2 0", "v 0 1 0", "f 1 2 3 4 5"]).unwrap();
+        
+        let triangles: Vec<[usize; 3]> = parser.buckets[0].1.iter()
+            .map(|triangle| triangle.map(|corner| corner.position))
+            .collect();
+        assert_eq!(triangles, vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+    
+    #[test]
+    fn errors_carry_the_line_number() {
+        let (line, message) = error_line(parse(&["v 0 0 0", "", "# comment", "v 1 x 0"]));
+        assert_eq!(line, 4);
+        assert!(message.contains("'x'"), "{}", message);
+        
+        let (line, message) = error_line(parse(&["v 0 0 0", "v 1 0 0", "f 1 2 3"]));
+        assert_eq!(line, 3);
+        assert!(message.contains("vertex index 3 is out of range"), "{}", message);
+        
+        let (line, message) = error_line(parse(&["v 0 0 0", "vn 0 0 0"]));
+        assert_eq!(line, 2);
+        assert!(message.contains("zero length"), "{}", message);
+    }
+    
+    #[test]
+    fn unknown_material_is_an_error() {
+        let (line, message) = error_line(parse(&["v 0 0 0", "usemtl missing"]));
+        assert_eq!(line, 2);
+        assert!(message.contains("unknown material 'missing'"), "{}", message);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::vector::*;
+use crate::rt_util;
+
+const POINT_COUNT: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>
+}
+
+impl Perlin {
+    pub fn new() -> Perlin {
+        let gradients = (0..POINT_COUNT).map(|_| Vec3::random_range(-1.0..1.0).normalized()).collect();
+        
+        Perlin {
+            gradients,
+            perm_x: Perlin::generate_perm(),
+            perm_y: Perlin::generate_perm(),
+            perm_z: Perlin::generate_perm()
+        }
+    }
+    
+    pub fn noise(&self, point: &Vec3) -> f64 {
+        let u = point.x - point.x.floor();
+        let v = point.y - point.y.floor();
+        let w = point.z - point.z.floor();
+        
+        let i = point.x.floor() as i64;
+        let j = point.y.floor() as i64;
+        let k = point.z.floor() as i64;
+        
+        let mut corners = [[[Vec3::new(0.0, 0.0, 0.0); 2]; 2]; 2];
+        for (di, plane) in corners.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[((i + di as i64) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i64) & 255) as usize];
+                    
+                    *corner = self.gradients[index];
+                }
+            }
+        }
+        
+        Perlin::trilinear_interp(&corners, u, v, w)
+    }
+    
+    // Sum of noise octaves with halving weights, gives the layered look used by marble and smoke
+    pub fn turbulence(&self, point: &Vec3, depth: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_point = *point;
+        let mut weight = 1.0;
+        
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_point);
+            weight *= 0.5;
+            temp_point = temp_point * 2.0;
+        }
+        
+        accum.abs()
+    }
+    
+    fn generate_perm() -> Vec<usize> {
+        let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+        
+        for i in (1..POINT_COUNT).rev() {
+            let target = (rt_util::random() * (i + 1) as f64) as usize;
+            perm.swap(i, target.min(i));
+        }
+        
+        perm
+    }
+    
+    fn trilinear_interp(corners: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        // Hermite smoothing removes the grid artifacts of plain linear interpolation
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+        
+        let mut accum = 0.0;
+        for (i, plane) in corners.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, gradient) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * dot(gradient, &weight);
+                }
+            }
+        }
+        
+        accum
+    }
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
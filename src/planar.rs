@@ -0,0 +1,260 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::primitive::*;
+use crate::ray::Ray;
+use crate::rt_util;
+use crate::vector::*;
+
+// Half size of the box given to infinite planes, large enough to cover any scene while keeping the
+// BVH's surface areas and centroids finite
+const PLANE_EXTENT: f64 = 1e9;
+
+// Parallelogram spanned by the edges u and v from the corner q, its UVs run from 0 to 1 along them
+#[derive(Clone, Debug)]
+pub struct Quad {
+    pub q: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Arc<dyn Material>,
+    
+    normal: Vec3,
+    // Scaled normal that turns a point on the plane into its coordinates along u and v
+    w: Vec3,
+    area: f64
+}
+
+impl Quad {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
+        let n = cross(&u, &v);
+        
+        Self {
+            q,
+            u,
+            v,
+            material,
+            normal: n.normalized(),
+            w: n * (1.0 / n.len_sqr()),
+            area: n.len()
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        let mut result = RayHitResult::default();
+        
+        let t = match intersect_plane(ray, &self.q, &self.normal, t_range) {
+            Some(t) => t,
+            None => return result
+        };
+        
+        let point = ray.at(t);
+        let planar = point - self.q;
+        let alpha = dot(&self.w, &cross(&planar, &self.v));
+        let beta = dot(&self.w, &cross(&self.u, &planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return result;
+        }
+        
+        result.is_hit = true;
+        result.data.ray_t = t;
+        result.data.point = point;
+        result.data.u = alpha;
+        result.data.v = beta;
+        result.data.material = Some(self.material.clone());
+        result.data.set_face_normal(ray, &self.normal);
+        
+        result
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        let corners = [self.q, self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        let min = corners.iter().fold(corners[0], |min, corner| min.min(corner));
+        let max = corners.iter().fold(corners[0], |max, corner| max.max(corner));
+        
+        padded_box(min, max)
+    }
+    
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f64) -> f64 {
+        area_pdf(self, self.area, origin, direction, time)
+    }
+    
+    fn random_direction(&self, origin: &Vec3, _time: f64) -> Vec3 {
+        let point = self.q + rt_util::random() * self.u + rt_util::random() * self.v;
+        
+        point - *origin
+    }
+}
+
+// The six faces of the box between two opposite corners, all facing outwards
+pub fn box_quads(a: &Vec3, b: &Vec3, material: Arc<dyn Material>) -> Vec<Quad> {
+    let min = a.min(b);
+    let max = a.max(b);
+    
+    let dx = Vec3::new(max.x - min.x, 0.0, 0.0);
+    let dy = Vec3::new(0.0, max.y - min.y, 0.0);
+    let dz = Vec3::new(0.0, 0.0, max.z - min.z);
+    
+    vec![
+        Quad::new(Vec3::new(min.x, min.y, max.z), dx, dy, material.clone()),
+        Quad::new(Vec3::new(max.x, min.y, max.z), -dz, dy, material.clone()),
+        Quad::new(Vec3::new(max.x, min.y, min.z), -dx, dy, material.clone()),
+        Quad::new(Vec3::new(min.x, min.y, min.z), dz, dy, material.clone()),
+        Quad::new(Vec3::new(min.x, max.y, max.z), dx, -dz, material.clone()),
+        Quad::new(Vec3::new(min.x, min.y, min.z), dx, dz, material)
+    ]
+}
+
+#[derive(Clone, Debug)]
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f64,
+    pub material: Arc<dyn Material>
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        let mut result = RayHitResult::default();
+        
+        let normal = self.normal.normalized();
+        let t = match intersect_plane(ray, &self.center, &normal, t_range) {
+            Some(t) => t,
+            None => return result
+        };
+        
+        let point = ray.at(t);
+        let offset = point - self.center;
+        if offset.len_sqr() > self.radius * self.radius {
+            return result;
+        }
+        
+        // Polar UVs, u goes around the rim and v out from the center
+        let local = Onb::new(&normal).world_to_local(&offset);
+        let angle = local.y.atan2(local.x);
+        
+        result.is_hit = true;
+        result.data.ray_t = t;
+        result.data.point = point;
+        result.data.u = (angle + rt_util::PI) / (2.0 * rt_util::PI);
+        result.data.v = offset.len() / self.radius;
+        result.data.material = Some(self.material.clone());
+        result.data.set_face_normal(ray, &normal);
+        
+        result
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        // The disk reaches radius * sin of the angle between its normal and each axis
+        let normal = self.normal.normalized();
+        let extent = Vec3::new(
+            self.radius * (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+            self.radius * (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+            self.radius * (1.0 - normal.z * normal.z).max(0.0).sqrt()
+        );
+        
+        padded_box(self.center - extent, self.center + extent)
+    }
+    
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f64) -> f64 {
+        area_pdf(self, rt_util::PI * self.radius * self.radius, origin, direction, time)
+    }
+    
+    fn random_direction(&self, origin: &Vec3, _time: f64) -> Vec3 {
+        let local = rt_util::random_in_unit_disk() * self.radius;
+        let point = self.center + Onb::new(&self.normal).local_to_world(&local);
+        
+        point - *origin
+    }
+}
+
+// Infinite plane through a point. It has no area to sample, so it can't be used as a light. UVs are
+// the distances along two directions in the plane, textures repeat through their wrap mode
+#[derive(Clone, Debug)]
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub material: Arc<dyn Material>
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        let mut result = RayHitResult::default();
+        
+        let normal = self.normal.normalized();
+        let t = match intersect_plane(ray, &self.point, &normal, t_range) {
+            Some(t) => t,
+            None => return result
+        };
+        
+        let point = ray.at(t);
+        let local = Onb::new(&normal).world_to_local(&(point - self.point));
+        
+        result.is_hit = true;
+        result.data.ray_t = t;
+        result.data.point = point;
+        result.data.u = local.x;
+        result.data.v = local.y;
+        result.data.material = Some(self.material.clone());
+        result.data.set_face_normal(ray, &normal);
+        
+        result
+    }
+    
+    // Thin along the axis the plane is perpendicular to, if any, and huge along the others
+    fn bounding_box(&self) -> Aabb {
+        let normal = self.normal.normalized();
+        let mut min = Vec3::new(-PLANE_EXTENT, -PLANE_EXTENT, -PLANE_EXTENT);
+        let mut max = Vec3::new(PLANE_EXTENT, PLANE_EXTENT, PLANE_EXTENT);
+        
+        if normal.y == 0.0 && normal.z == 0.0 {
+            (min.x, max.x) = (self.point.x, self.point.x);
+        }
+        else if normal.x == 0.0 && normal.z == 0.0 {
+            (min.y, max.y) = (self.point.y, self.point.y);
+        }
+        else if normal.x == 0.0 && normal.y == 0.0 {
+            (min.z, max.z) = (self.point.z, self.point.z);
+        }
+        
+        padded_box(min, max)
+    }
+}
+
+// Distance along the ray to the plane through `point`, None when the ray runs parallel to it
+fn intersect_plane(ray: &Ray, point: &Vec3, normal: &Vec3, t_range: Range<f64>) -> Option<f64> {
+    let denom = dot(normal, &ray.direction);
+    if denom.abs() < 1e-8 {
+        return None;
+    }
+    
+    let t = dot(normal, &(*point - ray.origin)) / denom;
+    if !t_range.contains(&t) {
+        return None;
+    }
+    
+    Some(t)
+}
+
+// Uniform area density of a flat shape converted to solid angle
+fn area_pdf(shape: &dyn Hittable, area: f64, origin: &Vec3, direction: &Vec3, time: f64) -> f64 {
+    let hit = shape.hit(&Ray::new(*origin, *direction, time), 0.001..rt_util::INFINITY);
+    if !hit.is_hit {
+        return 0.0;
+    }
+    
+    let dist_sqr = hit.data.ray_t * hit.data.ray_t * direction.len_sqr();
+    let cosine = dot(direction, &hit.data.normal).abs() / direction.len();
+    
+    dist_sqr / (cosine * area)
+}
+
+// Flat shapes still need a volume for the ray/box test
+fn padded_box(min: Vec3, max: Vec3) -> Aabb {
+    let padding = Vec3::new(1e-6, 1e-6, 1e-6);
+    
+    Aabb::new(min - padding, max + padding)
+}
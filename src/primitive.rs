@@ -1,15 +1,21 @@
 use std::ops::Range;
+use std::sync::Arc;
 
+use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::vector::*;
 use crate::ray::*;
+use crate::rt_util;
 
 pub struct RayHitData {
     pub point: Vec3,
     pub normal: Vec3,
     pub ray_t: f64,
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
-    pub material: Material
+    // Only None when nothing was hit
+    pub material: Option<Arc<dyn Material>>
 }
 
 impl RayHitData {
@@ -37,88 +43,371 @@ impl Default for RayHitResult {
                 point: Vec3 {x: 0.0, y: 0.0, z: 0.0},
                 normal: Vec3 {x: 0.0, y: 0.0, z: 0.0},
                 ray_t: -1.0,
+                u: 0.0,
+                v: 0.0,
                 front_face: false,
-                material: Material::default()
+                material: None
             }
         }
     }
 }
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult;
+    fn bounding_box(&self) -> Aabb;
+    
+    // Solid angle density of picking `direction` from `origin` with random_direction, for a ray
+    // leaving at `time`. Only shapes that can be sampled as lights need to override these
+    fn pdf_value(&self, _origin: &Vec3, _direction: &Vec3, _time: f64) -> f64 {
+        0.0
+    }
+    
+    fn random_direction(&self, _origin: &Vec3, _time: f64) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+    
+    // Closest surface along the ray with volumes letting it through, shadow rays pair it with
+    // transmittance() instead of stopping at random points inside volumes
+    fn hit_surface(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        self.hit(ray, t_range)
+    }
+    
+    // Fraction of light that makes it through the volumes along the ray, surfaces don't count
+    fn transmittance(&self, _ray: &Ray, _t_range: Range<f64>) -> f64 {
+        1.0
+    }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f64,
-    pub material: Material
+    pub material: Arc<dyn Material>
 }
 
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
-        let mut result = RayHitResult::default();
+        hit_sphere(&self.center, self.radius, &self.material, ray, t_range)
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        sphere_bounding_box(&self.center, self.radius)
+    }
+    
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f64) -> f64 {
+        if !self.hit(&Ray::new(*origin, *direction, time), 0.001..rt_util::INFINITY).is_hit {
+            return 0.0;
+        }
         
-        let sphere_to_ray = ray.origin - self.center;
+        sphere_cone_pdf(&self.center, self.radius, origin)
+    }
+    
+    fn random_direction(&self, origin: &Vec3, _time: f64) -> Vec3 {
+        sphere_cone_direction(&self.center, self.radius, origin)
+    }
+}
+
+// A sphere whose center moves through keyframes of (time, center), interpolated linearly between
+// them and held still before the first and after the last one
+#[derive(Clone, Debug)]
+pub struct MovingSphere {
+    keyframes: Vec<(f64, Vec3)>,
+    pub radius: f64,
+    pub material: Arc<dyn Material>
+}
+
+impl MovingSphere {
+    pub fn new(mut keyframes: Vec<(f64, Vec3)>, radius: f64, material: Arc<dyn Material>) -> Self {
+        assert!(!keyframes.is_empty(), "a moving sphere needs at least one keyframe");
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
         
-        let a = ray.direction.len_sqr();
-        let b = dot(&ray.direction, &sphere_to_ray);
-        let c = sphere_to_ray.len_sqr() - self.radius * self.radius;
+        Self {keyframes, radius, material}
+    }
+    
+    pub fn center(&self, time: f64) -> Vec3 {
+        let next = self.keyframes.partition_point(|(key_time, _)| *key_time <= time);
+        if next == 0 {
+            return self.keyframes[0].1;
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1].1;
+        }
         
-        let discriminant = b * b - a * c;
+        let (time_0, center_0) = self.keyframes[next - 1];
+        let (time_1, center_1) = self.keyframes[next];
+        let interp = (time - time_0) / (time_1 - time_0);
         
-        if discriminant < 0.0 {
-            return result;
+        center_0 + (center_1 - center_0) * interp
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        hit_sphere(&self.center(ray.time), self.radius, &self.material, ray, t_range)
+    }
+    
+    // The path between two keyframes is a straight line, so the boxes at the keyframes cover all of it
+    fn bounding_box(&self) -> Aabb {
+        self.keyframes.iter().fold(Aabb::empty(), |bbox, (_, center)| Aabb::surrounding(&bbox, &sphere_bounding_box(center, self.radius)))
+    }
+    
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f64) -> f64 {
+        if !self.hit(&Ray::new(*origin, *direction, time), 0.001..rt_util::INFINITY).is_hit {
+            return 0.0;
         }
         
-        let disc_sqrt = f64::sqrt(discriminant);
+        sphere_cone_pdf(&self.center(time), self.radius, origin)
+    }
+    
+    fn random_direction(&self, origin: &Vec3, time: f64) -> Vec3 {
+        sphere_cone_direction(&self.center(time), self.radius, origin)
+    }
+}
+
+fn hit_sphere(center: &Vec3, radius: f64, material: &Arc<dyn Material>, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+    let mut result = RayHitResult::default();
+    
+    let sphere_to_ray = ray.origin - *center;
+    
+    let a = ray.direction.len_sqr();
+    let b = dot(&ray.direction, &sphere_to_ray);
+    let c = sphere_to_ray.len_sqr() - radius * radius;
+    
+    let discriminant = b * b - a * c;
+    
+    if discriminant < 0.0 {
+        return result;
+    }
+    
+    let disc_sqrt = f64::sqrt(discriminant);
+    
+    let mut t = (-b - disc_sqrt) / a;
+    if !t_range.contains(&t) {
+        t = (-b + disc_sqrt) / a;
         
-        let mut t = (-b - disc_sqrt) / a;
         if !t_range.contains(&t) {
-            t = (-b + disc_sqrt) / a;
-            
-            if !t_range.contains(&t) {
-                return result;
-            }
+            return result;
         }
+    }
+    
+    result.is_hit = true;
+    result.data.ray_t = t;
+    result.data.point = ray.at(result.data.ray_t);
+    result.data.material = Some(material.clone());
+    
+    let normal = (result.data.point - *center) * (1.0 / radius);
+    result.data.set_face_normal(ray, &normal);
+    (result.data.u, result.data.v) = sphere_uv(&normal);
+    
+    result
+}
+
+fn sphere_bounding_box(center: &Vec3, radius: f64) -> Aabb {
+    let extent = Vec3::new(radius, radius, radius);
+    
+    Aabb::new(*center - extent, *center + extent)
+}
+
+fn sphere_cone_pdf(center: &Vec3, radius: f64, origin: &Vec3) -> f64 {
+    let dist_sqr = (*center - *origin).len_sqr();
+    let radius_sqr = radius * radius;
+    if dist_sqr <= radius_sqr {
+        return 1.0 / (4.0 * rt_util::PI);
+    }
+    
+    let cos_theta_max = (1.0 - radius_sqr / dist_sqr).sqrt();
+    
+    1.0 / (2.0 * rt_util::PI * (1.0 - cos_theta_max))
+}
+
+// Samples the cone of directions the sphere covers as seen from the origin
+fn sphere_cone_direction(center: &Vec3, radius: f64, origin: &Vec3) -> Vec3 {
+    let to_center = *center - *origin;
+    let dist_sqr = to_center.len_sqr();
+    let radius_sqr = radius * radius;
+    if dist_sqr <= radius_sqr {
+        return rt_util::random_on_unit_sphere();
+    }
+    
+    let cos_theta_max = (1.0 - radius_sqr / dist_sqr).sqrt();
+    let r1 = rt_util::random();
+    let r2 = rt_util::random();
+    let z = 1.0 + r2 * (cos_theta_max - 1.0);
+    let phi = 2.0 * rt_util::PI * r1;
+    let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+    
+    Onb::new(&to_center).local_to_world(&Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+}
+
+#[derive(Clone, Debug)]
+pub struct Triangle {
+    pub vertices: [Vec3; 3],
+    pub material: Arc<dyn Material>
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        let mut result = RayHitResult::default();
+        
+        let (t, barycentric) = match intersect_triangle(ray, &self.vertices, t_range) {
+            Some(intersection) => intersection,
+            None => return result
+        };
         
         result.is_hit = true;
         result.data.ray_t = t;
-        result.data.point = ray.at(result.data.ray_t);
-        result.data.material = self.material;
+        result.data.point = ray.at(t);
+        result.data.u = barycentric[1];
+        result.data.v = barycentric[2];
+        result.data.material = Some(self.material.clone());
         
-        let normal = (result.data.point - self.center) * (1.0 / self.radius);
+        let normal = cross(&(self.vertices[1] - self.vertices[0]), &(self.vertices[2] - self.vertices[0])).normalized();
         result.data.set_face_normal(ray, &normal);
         
         result
     }
+    
+    fn bounding_box(&self) -> Aabb {
+        triangle_bounding_box(&self.vertices)
+    }
+    
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f64) -> f64 {
+        let ray = Ray::new(*origin, *direction, time);
+        let hit = self.hit(&ray, 0.001..rt_util::INFINITY);
+        if !hit.is_hit {
+            return 0.0;
+        }
+        
+        let edges_cross = cross(&(self.vertices[1] - self.vertices[0]), &(self.vertices[2] - self.vertices[0]));
+        let area = 0.5 * edges_cross.len();
+        let dist_sqr = hit.data.ray_t * hit.data.ray_t * direction.len_sqr();
+        let cosine = dot(direction, &hit.data.normal).abs() / direction.len();
+        
+        // Uniform area density converted to solid angle
+        dist_sqr / (cosine * area)
+    }
+    
+    fn random_direction(&self, origin: &Vec3, _time: f64) -> Vec3 {
+        let mut r1 = rt_util::random();
+        let mut r2 = rt_util::random();
+        if r1 + r2 > 1.0 {
+            r1 = 1.0 - r1;
+            r2 = 1.0 - r2;
+        }
+        
+        let point = self.vertices[0] + r1 * (self.vertices[1] - self.vertices[0]) + r2 * (self.vertices[2] - self.vertices[0]);
+        
+        point - *origin
+    }
+}
+
+// Maps a point on the unit sphere to longitude/latitude UVs, u wraps around the y axis starting at -x
+// and v goes from the bottom pole to the top one
+pub fn sphere_uv(point: &Vec3) -> (f64, f64) {
+    let theta = f64::acos(-point.y.clamp(-1.0, 1.0));
+    let phi = f64::atan2(-point.z, point.x) + rt_util::PI;
+    
+    (phi / (2.0 * rt_util::PI), theta / rt_util::PI)
+}
+
+// Watertight ray/triangle intersection (Woop, Benthin, Wald 2013). Shared edges between triangles
+// never let a ray slip through, returns the ray distance and the barycentric weights of each vertex
+pub fn intersect_triangle(ray: &Ray, vertices: &[Vec3; 3], t_range: Range<f64>) -> Option<(f64, [f64; 3])> {
+    let dir_abs = Vec3::new(ray.direction.x.abs(), ray.direction.y.abs(), ray.direction.z.abs());
+    let kz = if dir_abs.x > dir_abs.y && dir_abs.x > dir_abs.z { 0 } else if dir_abs.y > dir_abs.z { 1 } else { 2 };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if ray.direction[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+    
+    let shear_x = ray.direction[kx] / ray.direction[kz];
+    let shear_y = ray.direction[ky] / ray.direction[kz];
+    let shear_z = 1.0 / ray.direction[kz];
+    
+    let a = vertices[0] - ray.origin;
+    let b = vertices[1] - ray.origin;
+    let c = vertices[2] - ray.origin;
+    
+    let ax = a[kx] - shear_x * a[kz];
+    let ay = a[ky] - shear_y * a[kz];
+    let bx = b[kx] - shear_x * b[kz];
+    let by = b[ky] - shear_y * b[kz];
+    let cx = c[kx] - shear_x * c[kz];
+    let cy = c[ky] - shear_y * c[kz];
+    
+    let edge_u = cx * by - cy * bx;
+    let edge_v = ax * cy - ay * cx;
+    let edge_w = bx * ay - by * ax;
+    
+    if (edge_u < 0.0 || edge_v < 0.0 || edge_w < 0.0) && (edge_u > 0.0 || edge_v > 0.0 || edge_w > 0.0) {
+        return None;
+    }
+    
+    let det = edge_u + edge_v + edge_w;
+    if det == 0.0 {
+        return None;
+    }
+    
+    let t_scaled = edge_u * shear_z * a[kz] + edge_v * shear_z * b[kz] + edge_w * shear_z * c[kz];
+    let t = t_scaled / det;
+    if !t_range.contains(&t) {
+        return None;
+    }
+    
+    let inv_det = 1.0 / det;
+    
+    Some((t, [edge_u * inv_det, edge_v * inv_det, edge_w * inv_det]))
+}
+
+pub fn triangle_bounding_box(vertices: &[Vec3; 3]) -> Aabb {
+    // Pad flat triangles so axis-aligned ones still have a volume to hit
+    let padding = Vec3::new(1e-6, 1e-6, 1e-6);
+    let min = vertices[0].min(&vertices[1]).min(&vertices[2]) - padding;
+    let max = vertices[0].max(&vertices[1]).max(&vertices[2]) + padding;
+    
+    Aabb::new(min, max)
 }
 
 pub struct HittableList {
-    objects: Vec<Box<dyn Hittable>>
+    objects: Vec<Box<dyn Hittable>>,
+    bbox: Aabb
 }
 
 impl HittableList {
     pub fn new() -> Self {
         Self {
-            objects: Vec::new()
+            objects: Vec::new(),
+            bbox: Aabb::empty()
         }
     }
     
     pub fn add(&mut self, object: Box<dyn Hittable>) {
+        self.bbox = Aabb::surrounding(&self.bbox, &object.bounding_box());
         self.objects.push(object);
     }
     
+    #[allow(dead_code)]
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.bbox = Aabb::empty();
+    }
+    
+    pub fn into_objects(self) -> Vec<Box<dyn Hittable>> {
+        self.objects
     }
     
-    pub fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+    
+    // Shared by hit and hit_surface, which only differ in how each object is tested
+    fn closest_hit(&self, ray: &Ray, t_range: Range<f64>, object_hit: impl Fn(&dyn Hittable, &Ray, Range<f64>) -> RayHitResult) -> RayHitResult {
         let mut hit: RayHitResult = RayHitResult::default();
         let mut closest_t: f64 = t_range.end;
         
         for object in self.objects.iter() {
-            let hit_result = object.hit(ray, t_range.start..closest_t);
+            let hit_result = object_hit(object.as_ref(), ray, t_range.start..closest_t);
             if hit_result.is_hit {
                 closest_t = hit_result.data.ray_t;
                 hit = hit_result;
@@ -128,3 +417,44 @@ impl HittableList {
         hit
     }
 }
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        self.closest_hit(ray, t_range, |object, ray, t_range| object.hit(ray, t_range))
+    }
+    
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+    
+    fn hit_surface(&self, ray: &Ray, t_range: Range<f64>) -> RayHitResult {
+        self.closest_hit(ray, t_range, |object, ray, t_range| object.hit_surface(ray, t_range))
+    }
+    
+    fn transmittance(&self, ray: &Ray, t_range: Range<f64>) -> f64 {
+        self.objects.iter().map(|object| object.transmittance(ray, t_range.clone())).product()
+    }
+    
+    // The list acts as a mixture of its objects, each picked with equal probability
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f64) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        
+        let sum: f64 = self.objects.iter().map(|object| object.pdf_value(origin, direction, time)).sum();
+        
+        sum / self.objects.len() as f64
+    }
+    
+    fn random_direction(&self, origin: &Vec3, time: f64) -> Vec3 {
+        let index = ((rt_util::random() * self.objects.len() as f64) as usize).min(self.objects.len() - 1);
+        
+        self.objects[index].random_direction(origin, time)
+    }
+}
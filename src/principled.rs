@@ -0,0 +1,369 @@
+use std::sync::Arc;
+
+use crate::color::Color01;
+use crate::material::*;
+use crate::microfacet::*;
+use crate::primitive::RayHitResult;
+use crate::ray::Ray;
+use crate::rt_util;
+use crate::texture::{self, Texture};
+use crate::vector::*;
+
+// The clearcoat keeps a fixed masking roughness whatever its gloss
+const CLEARCOAT_GEOMETRY: Ggx = Ggx {alpha: 0.25};
+
+// Disney's principled BSDF (Burley 2012 and 2015), one material whose parameters blend between
+// diffuse, metallic, glossy, clearcoated and glass looks. Every parameter is a texture, scalar
+// parameters read the average of the texture's channels
+#[derive(Clone, Debug)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    // Strength of the dielectric highlight, 0.5 reflects 4% at normal incidence
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub refraction_idx: Arc<dyn Texture>
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled {
+            base_color: texture::constant(0.8),
+            metallic: texture::constant(0.0),
+            roughness: texture::constant(0.5),
+            specular: texture::constant(0.5),
+            specular_tint: texture::constant(0.0),
+            sheen: texture::constant(0.0),
+            sheen_tint: texture::constant(0.5),
+            clearcoat: texture::constant(0.0),
+            clearcoat_gloss: texture::constant(1.0),
+            transmission: texture::constant(0.0),
+            refraction_idx: texture::constant(1.5)
+        }
+    }
+}
+
+// The parameters resolved at one hit, seen from one outgoing direction
+struct Lobes {
+    base_color: Color01,
+    roughness: f64,
+    diffuse_weight: f64,
+    specular_weight: f64,
+    transmission_weight: f64,
+    specular_f0: Color01,
+    sheen: Color01,
+    clearcoat: f64,
+    distribution: Ggx,
+    clearcoat_distribution: Gtr1,
+    eta: f64,
+    // Chance of sampling the diffuse, specular, clearcoat and transmission lobes
+    probabilities: [f64; 4]
+}
+
+impl Principled {
+    fn lobes(&self, hit: &RayHitResult, wo: &Vec3) -> Lobes {
+        let base_color = self.base_color.value(hit.data.u, hit.data.v, &hit.data.point);
+        let metallic = scalar_at(&self.metallic, hit).clamp(0.0, 1.0);
+        let roughness = scalar_at(&self.roughness, hit).clamp(0.0, 1.0);
+        let specular = scalar_at(&self.specular, hit).max(0.0);
+        let specular_tint = scalar_at(&self.specular_tint, hit).clamp(0.0, 1.0);
+        let sheen = scalar_at(&self.sheen, hit).max(0.0);
+        let sheen_tint = scalar_at(&self.sheen_tint, hit).clamp(0.0, 1.0);
+        let clearcoat = scalar_at(&self.clearcoat, hit).max(0.0);
+        let clearcoat_gloss = scalar_at(&self.clearcoat_gloss, hit).clamp(0.0, 1.0);
+        let transmission = scalar_at(&self.transmission, hit).clamp(0.0, 1.0);
+        let refraction_idx = scalar_at(&self.refraction_idx, hit).max(1e-3);
+        
+        // Hue and saturation of the base color, tints are applied without darkening
+        let white = Color01::new(1.0, 1.0, 1.0);
+        let luminance = base_color.luminance();
+        let tint = if luminance > 0.0 { base_color * (1.0 / luminance) } else { white };
+        
+        let dielectric_f0 = lerp(white, tint, specular_tint) * (0.08 * specular);
+        let specular_f0 = lerp(dielectric_f0, base_color, metallic);
+        let sheen = lerp(white, tint, sheen_tint) * sheen;
+        
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        let transmission_weight = (1.0 - metallic) * transmission;
+        // The glass lobe brings its own reflection, the plain highlight covers the rest
+        let specular_weight = 1.0 - transmission_weight;
+        let clearcoat = 0.25 * clearcoat;
+        
+        let mut probabilities = [
+            diffuse_weight * (base_color + sheen).luminance(),
+            specular_weight * fresnel_schlick(wo.z, specular_f0).luminance(),
+            clearcoat * fresnel_schlick(wo.z, Color01::new(0.04, 0.04, 0.04)).r,
+            transmission_weight
+        ];
+        let total: f64 = probabilities.iter().sum();
+        if total > 0.0 {
+            probabilities.iter_mut().for_each(|probability| *probability /= total);
+        }
+        else {
+            probabilities = [1.0, 0.0, 0.0, 0.0];
+        }
+        
+        Lobes {
+            base_color,
+            roughness,
+            diffuse_weight,
+            specular_weight,
+            transmission_weight,
+            specular_f0,
+            sheen,
+            clearcoat,
+            distribution: Ggx::from_roughness(roughness),
+            clearcoat_distribution: Gtr1 {alpha: 0.1 + (0.001 - 0.1) * clearcoat_gloss},
+            eta: relative_idx(refraction_idx, hit),
+            probabilities
+        }
+    }
+}
+
+impl Lobes {
+    fn sample(&self, wo: &Vec3) -> Option<Vec3> {
+        let choice = rt_util::random();
+        let [diffuse, specular, clearcoat, _] = self.probabilities;
+        
+        if choice < diffuse {
+            let direction = Vec3::new(0.0, 0.0, 1.0) + rt_util::random_on_unit_sphere();
+            if direction.near_zero() {
+                Some(Vec3::new(0.0, 0.0, 1.0))
+            }
+            else {
+                Some(direction.normalized())
+            }
+        }
+        else if choice < diffuse + specular {
+            reflect_above(wo, &self.distribution.sample_visible_normal(wo))
+        }
+        else if choice < diffuse + specular + clearcoat {
+            reflect_above(wo, &self.clearcoat_distribution.sample_normal())
+        }
+        else {
+            self.distribution.sample_dielectric(wo, self.eta)
+        }
+    }
+    
+    // Density of sample() over all lobes, so any lobe may have produced `wi`
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        
+        let [diffuse, specular, clearcoat, transmission] = self.probabilities;
+        let mut pdf = transmission * self.distribution.dielectric_pdf(wo, wi, self.eta);
+        
+        if wi.z > 0.0 {
+            pdf += diffuse * wi.z / rt_util::PI;
+            
+            let half = (*wo + *wi).normalized();
+            let cos_oh = dot(wo, &half);
+            if cos_oh > 0.0 {
+                pdf += specular * self.distribution.visible_normal_pdf(wo, &half) / (4.0 * cos_oh);
+                pdf += clearcoat * self.clearcoat_distribution.normal_pdf(&half) / (4.0 * cos_oh);
+            }
+        }
+        
+        pdf
+    }
+    
+    // BSDF times the cosine of `wi`, summed over all lobes
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color01 {
+        if wo.z <= 0.0 {
+            return Color01::default();
+        }
+        
+        let mut value = Color01::default();
+        
+        if wi.z > 0.0 {
+            let half = (*wo + *wi).normalized();
+            let cos_d = dot(wi, &half);
+            
+            if self.diffuse_weight > 0.0 {
+                // Burley diffuse, darkens at grazing angles for smooth surfaces and adds
+                // retro-reflection for rough ones
+                let fl = schlick_weight(wi.z);
+                let fv = schlick_weight(wo.z);
+                let retro = 2.0 * self.roughness * cos_d * cos_d;
+                let lambert = (1.0 - 0.5 * fl) * (1.0 - 0.5 * fv);
+                let retro_reflection = retro * (fl + fv + fl * fv * (retro - 1.0));
+                
+                let diffuse = self.base_color * ((lambert + retro_reflection) / rt_util::PI) + self.sheen * schlick_weight(cos_d);
+                value += diffuse * (self.diffuse_weight * wi.z);
+            }
+            
+            let dg = self.distribution.d(&half) * self.distribution.g2(wo, wi);
+            value += fresnel_schlick(cos_d, self.specular_f0) * (self.specular_weight * dg / (4.0 * wo.z));
+            
+            if self.clearcoat > 0.0 {
+                let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+                let g = CLEARCOAT_GEOMETRY.g2(wo, wi);
+                let clearcoat = self.clearcoat * fresnel * self.clearcoat_distribution.d(&half) * g / (4.0 * wo.z);
+                value += Color01::new(clearcoat, clearcoat, clearcoat);
+            }
+        }
+        
+        if self.transmission_weight > 0.0 {
+            let glass = self.transmission_weight * self.distribution.dielectric_eval(wo, wi, self.eta);
+            
+            // Only light passing through the surface picks up the base color
+            value += if wi.z < 0.0 { self.base_color * glass } else { Color01::new(glass, glass, glass) };
+        }
+        
+        value
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, ray: &Ray, hit: &RayHitResult) -> ScatterRecord {
+        let frame = Onb::new(&hit.data.normal);
+        let wo = frame.world_to_local(&-ray.direction.normalized());
+        if wo.z <= 0.0 {
+            return ScatterRecord::absorbed(Color01::default());
+        }
+        
+        let lobes = self.lobes(hit, &wo);
+        let wi = match lobes.sample(&wo) {
+            Some(wi) => wi,
+            None => return ScatterRecord::absorbed(Color01::default())
+        };
+        
+        let pdf = lobes.pdf(&wo, &wi);
+        let value = lobes.eval(&wo, &wi);
+        if pdf <= 0.0 || value.max_component() <= 0.0 {
+            return ScatterRecord::absorbed(Color01::default());
+        }
+        
+        ScatterRecord::diffuse(Ray::new(hit.data.point, frame.local_to_world(&wi), ray.time), value * (1.0 / pdf), pdf)
+    }
+    
+    fn scattering_pdf(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> f64 {
+        let (wo, wi) = local_directions(ray, hit, direction);
+        
+        self.lobes(hit, &wo).pdf(&wo, &wi)
+    }
+    
+    fn eval(&self, ray: &Ray, hit: &RayHitResult, direction: &Vec3) -> Color01 {
+        let (wo, wi) = local_directions(ray, hit, direction);
+        
+        self.lobes(hit, &wo).eval(&wo, &wi)
+    }
+}
+
+fn scalar_at(texture: &Arc<dyn Texture>, hit: &RayHitResult) -> f64 {
+    let value = texture.value(hit.data.u, hit.data.v, &hit.data.point);
+    
+    (value.r + value.g + value.b) / 3.0
+}
+
+fn lerp(a: Color01, b: Color01, t: f64) -> Color01 {
+    a * (1.0 - t) + b * t
+}
+
+// Steep microfacets can reflect into the surface, where the glass lobe would be evaluated in place
+// of the highlight
+fn reflect_above(wo: &Vec3, m: &Vec3) -> Option<Vec3> {
+    let wi = (-*wo).reflect(m);
+    if wi.z <= 0.0 {
+        return None;
+    }
+    
+    Some(wi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::microfacet::tests::{assert_samples_match_pdf, integrate_over_sphere};
+    
+    fn lobes(material: &Principled, wo: &Vec3) -> Lobes {
+        let mut hit = RayHitResult::default();
+        hit.data.front_face = true;
+        
+        material.lobes(&hit, wo)
+    }
+    
+    fn materials() -> Vec<Principled> {
+        vec![
+            Principled::default(),
+            Principled {metallic: texture::constant(1.0), roughness: texture::constant(0.4), ..Principled::default()},
+            Principled {clearcoat: texture::constant(1.0), clearcoat_gloss: texture::constant(0.0), ..Principled::default()},
+            Principled {transmission: texture::constant(1.0), roughness: texture::constant(0.3), ..Principled::default()},
+            Principled {metallic: texture::constant(0.3), transmission: texture::constant(0.6), clearcoat: texture::constant(0.5),
+                clearcoat_gloss: texture::constant(0.2), sheen: texture::constant(1.0), ..Principled::default()}
+        ]
+    }
+    
+    fn view_direction(cos_theta: f64) -> Vec3 {
+        Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta)
+    }
+    
+    #[test]
+    fn pdf_integrates_to_one() {
+        rt_util::seed_rng(5);
+        for material in materials() {
+            for cos_theta in [0.9, 0.5] {
+                let wo = view_direction(cos_theta);
+                let lobes = lobes(&material, &wo);
+                let integral = integrate_over_sphere(|wi| lobes.pdf(&wo, wi));
+                
+                // Samples dropped for leaving on the wrong side of the surface are all that is missing
+                let steps = 100_000;
+                let kept = (0..steps).filter(|_| lobes.sample(&wo).is_some()).count() as f64 / steps as f64;
+                assert!((integral - kept).abs() < 0.01, "{:?} at cos {}: {} against {}", lobes.probabilities, cos_theta, integral, kept);
+                assert!(integral > 0.95, "{:?} at cos {}: {}", lobes.probabilities, cos_theta, integral);
+            }
+        }
+    }
+    
+    #[test]
+    fn samples_match_pdf() {
+        for material in materials() {
+            let wo = view_direction(0.7);
+            let lobes = lobes(&material, &wo);
+            
+            assert_samples_match_pdf(6, || lobes.sample(&wo), |wi| lobes.pdf(&wo, wi));
+        }
+    }
+    
+    // Mean of eval / pdf over sampled directions, the share of the light that is scattered
+    fn albedo(lobes: &Lobes, wo: &Vec3) -> Color01 {
+        let steps = 100_000;
+        let mut sum = Color01::default();
+        for _ in 0..steps {
+            if let Some(wi) = lobes.sample(wo) {
+                sum += lobes.eval(wo, &wi) * (1.0 / lobes.pdf(wo, &wi));
+            }
+        }
+        
+        sum * (1.0 / steps as f64)
+    }
+    
+    #[test]
+    fn white_metal_matches_the_microfacet_albedo() {
+        rt_util::seed_rng(7);
+        let wo = view_direction(0.8);
+        for roughness in [0.2, 0.6, 1.0] {
+            let material = Principled {base_color: texture::constant(1.0), metallic: texture::constant(1.0), roughness: texture::constant(roughness),
+                ..Principled::default()};
+            let albedo = albedo(&lobes(&material, &wo), &wo);
+            
+            // Reflecting visible normals weighs each direction by G2 / G1 alone
+            let distribution = Ggx::from_roughness(roughness);
+            let steps = 100_000;
+            let expected = (0..steps).map(|_| {
+                let wi = (-wo).reflect(&distribution.sample_visible_normal(&wo));
+                if wi.z > 0.0 { distribution.g2(&wo, &wi) / distribution.g1(&wo) } else { 0.0 }
+            }).sum::<f64>() / steps as f64;
+            
+            assert!((albedo.r - expected).abs() < 0.01 && albedo.r == albedo.b, "roughness {}: {:?} against {}", roughness, albedo, expected);
+        }
+    }
+}
@@ -3,14 +3,20 @@ use crate::vector::Vec3;
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Ray {
     pub origin: Vec3,
-    pub direction: Vec3
+    pub direction: Vec3,
+    // Instant within the camera shutter the ray travels at, moving objects are placed at this time
+    pub time: f64,
+    // Hero wavelength in nanometers of spectral renders, None when rendering in RGB
+    pub wavelength: Option<f64>
 }
 
 impl Ray {
-    pub fn new(orig: Vec3, dir: Vec3) -> Ray {
+    pub fn new(orig: Vec3, dir: Vec3, time: f64) -> Ray {
         Ray {
             origin: orig,
-            direction: dir
+            direction: dir,
+            time,
+            wavelength: None
         }
     }
     
@@ -1,9 +1,14 @@
 #![allow(dead_code)]
 
-use rand::Rng;
+use std::cell::RefCell;
+use std::ops::Range;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
 
 use crate::vector::{Vec3, dot};
 
+#[allow(clippy::approx_constant, clippy::excessive_precision)]
 pub const PI: f64 = 3.1415926535897932385;
 pub const INFINITY: f64 = f64::INFINITY;
 
@@ -15,16 +20,46 @@ pub fn rad_to_deg(rad: f64) -> f64 {
     rad * 180.0 / PI
 }
 
-pub fn print_image_header(width: u32, height: u32) {
-    println!("P3");
-    println!("{} {}", width, height);
-    println!("255");
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::seed_from_u64(0));
 }
 
-pub fn random() -> f64 {
-    let mut rng = rand::thread_rng();
+// Restarts the calling thread's random stream, every draw after this is fully determined by the seed
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+// Combines a base seed with stream indices (pixel, sample, ...) into a well distributed seed
+pub fn hash_seed(seed: u64, indices: &[u64]) -> u64 {
+    indices.iter().fold(splitmix64(seed), |hash, index| splitmix64(hash ^ index))
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    
+    z ^ (z >> 31)
+}
+
+// Multiple importance sampling weight for a sample drawn from strategy a (Veach's power heuristic, beta = 2)
+pub fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 {
+    let a_sqr = pdf_a * pdf_a;
+    let b_sqr = pdf_b * pdf_b;
     
-    rng.gen()
+    if a_sqr + b_sqr <= 0.0 {
+        return 0.0;
+    }
+    
+    a_sqr / (a_sqr + b_sqr)
+}
+
+pub fn random() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+pub fn random_range(range: Range<f64>) -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen_range(range))
 }
 
 pub fn random_in_unit_disk() -> Vec3 {
@@ -48,6 +83,10 @@ pub fn random_in_unit_sphere() -> Vec3 {
     }
 }
 
+pub fn random_on_unit_sphere() -> Vec3 {
+    random_in_unit_sphere().normalized()
+}
+
 pub fn random_on_unit_hemisphere(normal: &Vec3) -> Vec3 {
     let point = random_in_unit_sphere().normalized();
     let is_correct_hs = dot(normal, &point) > 0.0;
@@ -0,0 +1,1204 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::camera::*;
+use crate::color::Color01;
+use crate::environment::EnvironmentMap;
+use crate::image::{Image, ImageFormat};
+use crate::instance::Instance;
+use crate::light::DeltaLight;
+use crate::material::*;
+use crate::medium::*;
+use crate::matrix::Mat4;
+use crate::obj::{self, ObjError};
+use crate::planar::*;
+use crate::primitive::*;
+use crate::principled::Principled;
+use crate::rt_util;
+use crate::sky::SunSky;
+use crate::spectrum::*;
+use crate::texture::{self, *};
+use crate::vector::{cross, Vec3};
+use crate::voxel::VoxelGrid;
+
+pub struct Scene {
+    pub camera: Camera,
+    pub world: HittableList,
+    // Copies of the emissive objects in the world, sampled directly while rendering
+    pub lights: HittableList,
+    // Point, spot and directional lights, they have no geometry and are not part of the world
+    pub delta_lights: Vec<DeltaLight>,
+    pub output: OutputSettings
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputSettings {
+    pub path: PathBuf,
+    pub format: ImageFormat
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        error: io::Error
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String
+    },
+    Obj(ObjError)
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io {path, error} => write!(f, "{}: {}", path.display(), error),
+            SceneError::Parse {path, line, column, message} => write!(f, "{}:{}:{}: {}", path.display(), line, column, message),
+            SceneError::Obj(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<ObjError> for SceneError {
+    fn from(error: ObjError) -> Self {
+        SceneError::Obj(error)
+    }
+}
+
+// Reads a TOML scene description. Relative paths inside it (meshes, image textures, output) are
+// resolved against the scene file's directory. A seed replaces the scene's own before anything random
+// is built
+pub fn load_scene(path: &Path, seed: Option<u64>) -> Result<Scene, SceneError> {
+    let source = fs::read_to_string(path).map_err(|error| SceneError::Io {path: path.to_path_buf(), error})?;
+    
+    parse_scene(path, &source, seed)
+}
+
+// Relative paths in the source are resolved against the directory of path
+fn parse_scene(path: &Path, source: &str, seed: Option<u64>) -> Result<Scene, SceneError> {
+    let loader = SceneLoader {path, source, seed};
+    
+    let desc: SceneDesc = toml::from_str(source).map_err(|error| {
+        let span = error.span().unwrap_or(0..0);
+        loader.error(span, error.message().to_string())
+    })?;
+    
+    loader.build(desc)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    #[serde(default)]
+    camera: CameraDesc,
+    #[serde(default)]
+    output: OutputDesc,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: BTreeMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+    #[serde(default)]
+    moving_spheres: Vec<MovingSphereDesc>,
+    #[serde(default)]
+    triangles: Vec<TriangleDesc>,
+    #[serde(default)]
+    quads: Vec<QuadDesc>,
+    #[serde(default)]
+    boxes: Vec<BoxDesc>,
+    #[serde(default)]
+    disks: Vec<DiskDesc>,
+    #[serde(default)]
+    planes: Vec<PlaneDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>,
+    // Named geometry that is only rendered through instances
+    #[serde(default)]
+    objects: BTreeMap<String, GeometryDesc>,
+    #[serde(default)]
+    instances: Vec<InstanceDesc>,
+    #[serde(default)]
+    media: Vec<MediumDesc>,
+    #[serde(default)]
+    volumes: Vec<VolumeDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GeometryDesc {
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+    #[serde(default)]
+    moving_spheres: Vec<MovingSphereDesc>,
+    #[serde(default)]
+    triangles: Vec<TriangleDesc>,
+    #[serde(default)]
+    quads: Vec<QuadDesc>,
+    #[serde(default)]
+    boxes: Vec<BoxDesc>,
+    #[serde(default)]
+    disks: Vec<DiskDesc>,
+    #[serde(default)]
+    planes: Vec<PlaneDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>
+}
+
+// Fills the inside of a closed object with a constant density of a volume material
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MediumDesc {
+    object: Spanned<String>,
+    density: Spanned<f64>,
+    material: Spanned<String>
+}
+
+// Box filled with a voxel grid of densities, read from a Mitsuba .vol file or generated from noise
+// when no grid is given. Grid values are multiplied by `density`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VolumeDesc {
+    min: [f64; 3],
+    max: Spanned<[f64; 3]>,
+    density: Spanned<f64>,
+    material: Spanned<String>,
+    grid: Option<String>,
+    resolution: Option<Spanned<usize>>,
+    frequency: Option<f64>,
+    emission: Option<VolumeEmissionDesc>
+}
+
+// Intensity follows the density unless it has its own grid. A temperature grid in Kelvin, or a range
+// spread from the thinnest to the densest voxels, replaces the color with blackbody colors
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VolumeEmissionDesc {
+    scale: Option<f64>,
+    color: Option<[f64; 3]>,
+    grid: Option<String>,
+    temperature_grid: Option<Spanned<String>>,
+    temperature: Option<[f64; 2]>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LightDesc {
+    #[serde(rename = "type")]
+    type_name: Spanned<String>,
+    position: Option<[f64; 3]>,
+    // The way the light travels, away from a spot light and towards the scene for a directional one
+    direction: Option<[f64; 3]>,
+    color: Option<[f64; 3]>,
+    intensity: Option<f64>,
+    // Spot light cone half angle and the width of its soft edge, in degrees
+    cone_angle: Option<f64>,
+    falloff_angle: Option<f64>
+}
+
+// Applied to the object as scale, then rotations around x, y and z in degrees, then translation
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceDesc {
+    object: Spanned<String>,
+    scale: Option<[f64; 3]>,
+    rotate: Option<[f64; 3]>,
+    translate: Option<[f64; 3]>
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    position: Option<[f64; 3]>,
+    look_at: Option<[f64; 3]>,
+    up_vector: Option<[f64; 3]>,
+    fov_vertical: Option<f64>,
+    defocus_angle: Option<f64>,
+    focus_distance: Option<f64>,
+    shutter_open: Option<f64>,
+    shutter_close: Option<f64>,
+    aspect_ratio: Option<f64>,
+    frame_width: Option<u32>,
+    samples_per_pixel: Option<u16>,
+    ray_bounces_max: Option<u16>,
+    roulette_depth: Option<u16>,
+    seed: Option<u64>,
+    light_sampling: Option<Spanned<String>>,
+    spectral: Option<bool>,
+    background: Option<BackgroundDesc>,
+    atmosphere: Option<AtmosphereDesc>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AtmosphereDesc {
+    density: Spanned<f64>,
+    albedo: Option<[f64; 3]>,
+    anisotropy: Option<Spanned<f64>>,
+    radius: Option<Spanned<f64>>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BackgroundDesc {
+    #[serde(rename = "type")]
+    type_name: Spanned<String>,
+    color: Option<[f64; 3]>,
+    bottom: Option<[f64; 3]>,
+    top: Option<[f64; 3]>,
+    // Equirectangular .hdr or .pfm image, turned by `rotation` degrees around the up axis
+    path: Option<String>,
+    rotation: Option<f64>,
+    intensity: Option<f64>,
+    // Sun position in degrees, an azimuth of 0 faces -z and 90 faces +x
+    elevation: Option<f64>,
+    azimuth: Option<f64>,
+    turbidity: Option<f64>,
+    sun_radius: Option<f64>
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OutputDesc {
+    path: Option<String>,
+    format: Option<Spanned<String>>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+    #[serde(rename = "type")]
+    type_name: Spanned<String>,
+    color: Option<[f64; 3]>,
+    scale: Option<f64>,
+    even: Option<[f64; 3]>,
+    odd: Option<[f64; 3]>,
+    path: Option<String>,
+    wrap: Option<Spanned<String>>,
+    kind: Option<Spanned<String>>
+}
+
+// Fields are only plain values here, serde buffers the table to find the type so toml spans get lost.
+// Errors are reported at the material's table instead
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambert {
+        albedo: Option<[f64; 3]>,
+        texture: Option<String>
+    },
+    Metal {
+        albedo: Option<[f64; 3]>,
+        texture: Option<String>,
+        fuzziness: Option<f64>
+    },
+    Dielectric {
+        refraction_idx: Option<f64>,
+        // Wavelength dependent index, in place of `refraction_idx`
+        dispersion: Option<DispersionDesc>
+    },
+    // Complex index of refraction per channel, or a measured metal's for every wavelength
+    Conductor {
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        preset: Option<String>,
+        roughness: Option<f64>
+    },
+    RoughDielectric {
+        refraction_idx: Option<f64>,
+        roughness: Option<f64>
+    },
+    Principled(Box<PrincipledDesc>),
+    Isotropic {
+        albedo: Option<[f64; 3]>,
+        texture: Option<String>
+    },
+    HenyeyGreenstein {
+        albedo: Option<[f64; 3]>,
+        texture: Option<String>,
+        // Asymmetry of volume scattering
+        anisotropy: Option<f64>
+    },
+    DiffuseLight {
+        albedo: Option<[f64; 3]>,
+        texture: Option<String>,
+        intensity: Option<f64>
+    }
+}
+
+// Coefficients are for wavelengths in micrometers
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DispersionDesc {
+    Cauchy {
+        a: f64,
+        b: f64
+    },
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3]
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrincipledDesc {
+    albedo: Option<[f64; 3]>,
+    texture: Option<String>,
+    metallic: Option<f64>,
+    roughness: Option<f64>,
+    specular: Option<f64>,
+    specular_tint: Option<f64>,
+    sheen: Option<f64>,
+    sheen_tint: Option<f64>,
+    clearcoat: Option<f64>,
+    clearcoat_gloss: Option<f64>,
+    transmission: Option<f64>,
+    refraction_idx: Option<f64>,
+    // Textures driving the parameters, they replace the matching plain value
+    maps: Option<PrincipledMapsDesc>
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrincipledMapsDesc {
+    metallic: Option<String>,
+    roughness: Option<String>,
+    specular: Option<String>,
+    specular_tint: Option<String>,
+    sheen: Option<String>,
+    sheen_tint: Option<String>,
+    clearcoat: Option<String>,
+    clearcoat_gloss: Option<String>,
+    transmission: Option<String>,
+    refraction_idx: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f64; 3],
+    radius: f64,
+    material: Spanned<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MovingSphereDesc {
+    // Two keyframes give a straight line, more give a path through all of them
+    keyframes: Spanned<Vec<KeyframeDesc>>,
+    radius: f64,
+    material: Spanned<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f64,
+    center: [f64; 3]
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriangleDesc {
+    vertices: [[f64; 3]; 3],
+    material: Spanned<String>
+}
+
+// Parallelogram from the corner along the u and v edges
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuadDesc {
+    corner: [f64; 3],
+    u: [f64; 3],
+    v: Spanned<[f64; 3]>,
+    material: Spanned<String>
+}
+
+// Axis-aligned box between two opposite corners
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoxDesc {
+    min: [f64; 3],
+    max: Spanned<[f64; 3]>,
+    material: Spanned<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiskDesc {
+    center: [f64; 3],
+    normal: Spanned<[f64; 3]>,
+    radius: Spanned<f64>,
+    material: Spanned<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaneDesc {
+    point: [f64; 3],
+    normal: Spanned<[f64; 3]>,
+    material: Spanned<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    path: String,
+    // Used for faces that don't pick a material with usemtl
+    material: Option<Spanned<String>>
+}
+
+// An object's geometry and its emissive parts, if it has any
+type SharedObject = (Arc<dyn Hittable>, Option<Arc<dyn Hittable>>);
+
+struct SceneLoader<'a> {
+    path: &'a Path,
+    source: &'a str,
+    seed: Option<u64>
+}
+
+impl SceneLoader<'_> {
+    fn error(&self, span: Range<usize>, message: String) -> SceneError {
+        let offset = span.start.min(self.source.len());
+        let before = &self.source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        
+        SceneError::Parse {path: self.path.to_path_buf(), line, column, message}
+    }
+    
+    fn resolve_path(&self, relative: &str) -> PathBuf {
+        self.path.parent().unwrap_or(Path::new("")).join(relative)
+    }
+    
+    fn build(&self, desc: SceneDesc) -> Result<Scene, SceneError> {
+        let mut camera = self.build_camera(&desc.camera)?;
+        if let Some(seed) = self.seed {
+            camera.seed = seed;
+        }
+        let output = self.build_output(&desc.output)?;
+        
+        // Procedural textures draw random numbers, seed them so the scene always builds the same way
+        rt_util::seed_rng(camera.seed);
+        
+        let mut textures: HashMap<&str, Arc<dyn Texture>> = HashMap::new();
+        for (name, texture_desc) in desc.textures.iter() {
+            textures.insert(name, self.build_texture(texture_desc)?);
+        }
+        
+        let mut materials: HashMap<&str, Arc<dyn Material>> = HashMap::new();
+        for (name, material_desc) in desc.materials.iter() {
+            materials.insert(name, self.build_material(material_desc, &textures)?);
+        }
+        
+        let geometry = GeometryDesc {
+            spheres: desc.spheres,
+            moving_spheres: desc.moving_spheres,
+            triangles: desc.triangles,
+            quads: desc.quads,
+            boxes: desc.boxes,
+            disks: desc.disks,
+            planes: desc.planes,
+            meshes: desc.meshes
+        };
+        let (mut world, mut lights) = self.build_geometry(&geometry, &materials)?;
+        
+        // Each object is built once and shared by all of its instances
+        let mut objects: HashMap<&str, SharedObject> = HashMap::new();
+        for (name, object_desc) in desc.objects.iter() {
+            let (object_world, object_lights) = self.build_geometry(object_desc, &materials)?;
+            let object_lights: Option<Arc<dyn Hittable>> = if object_lights.is_empty() { None } else { Some(Arc::new(object_lights)) };
+            
+            objects.insert(name, (Arc::new(BvhNode::new(object_world)), object_lights));
+        }
+        
+        for instance in desc.instances.iter() {
+            let (object, object_lights) = self.lookup(&objects, &instance.object, "object")?;
+            let transform = instance_transform(instance);
+            
+            let singular = || self.error(instance.object.span(), String::from("instance transform can't be inverted, check for a zero scale"));
+            world.add(Box::new(Instance::new(object, transform).ok_or_else(singular)?));
+            if let Some(object_lights) = object_lights {
+                lights.add(Box::new(Instance::new(object_lights, transform).ok_or_else(singular)?));
+            }
+        }
+        
+        for medium in desc.media.iter() {
+            let (boundary, _) = self.lookup(&objects, &medium.object, "object")?;
+            let phase = self.lookup(&materials, &medium.material, "material")?;
+            
+            world.add(Box::new(ConstantMedium::new(boundary, self.positive(&medium.density, "medium density")?, phase)));
+        }
+        
+        for volume in desc.volumes.iter() {
+            world.add(Box::new(self.build_volume(volume, &materials)?));
+        }
+        
+        let delta_lights = desc.lights.iter().map(|light| self.build_light(light)).collect::<Result<Vec<_>, _>>()?;
+        
+        Ok(Scene {camera, world, lights, delta_lights, output})
+    }
+    
+    // Returns the world objects and the emissive ones among them to sample as lights
+    fn build_geometry(&self, geometry: &GeometryDesc, materials: &HashMap<&str, Arc<dyn Material>>) -> Result<(HittableList, HittableList), SceneError> {
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        
+        for sphere in geometry.spheres.iter() {
+            let sphere = Sphere {
+                center: to_vec3(sphere.center),
+                radius: sphere.radius,
+                material: self.lookup(materials, &sphere.material, "material")?
+            };
+            
+            if sphere.material.is_emissive() {
+                lights.add(Box::new(sphere.clone()));
+            }
+            world.add(Box::new(sphere));
+        }
+        
+        for sphere in geometry.moving_spheres.iter() {
+            if sphere.keyframes.get_ref().is_empty() {
+                return Err(self.error(sphere.keyframes.span(), String::from("moving spheres need at least one keyframe")));
+            }
+            
+            let keyframes = sphere.keyframes.get_ref().iter().map(|keyframe| (keyframe.time, to_vec3(keyframe.center))).collect();
+            let sphere = MovingSphere::new(keyframes, sphere.radius, self.lookup(materials, &sphere.material, "material")?);
+            
+            if sphere.material.is_emissive() {
+                lights.add(Box::new(sphere.clone()));
+            }
+            world.add(Box::new(sphere));
+        }
+        
+        for triangle in geometry.triangles.iter() {
+            let triangle = Triangle {
+                vertices: triangle.vertices.map(to_vec3),
+                material: self.lookup(materials, &triangle.material, "material")?
+            };
+            
+            if triangle.material.is_emissive() {
+                lights.add(Box::new(triangle.clone()));
+            }
+            world.add(Box::new(triangle));
+        }
+        
+        for quad in geometry.quads.iter() {
+            let (u, v) = (to_vec3(quad.u), to_vec3(*quad.v.get_ref()));
+            // Also catches a zero u or v
+            if cross(&u, &v).len() <= 1e-9 * u.len() * v.len() {
+                return Err(self.error(quad.v.span(), String::from("quad u and v must be non-zero and not parallel")));
+            }
+            
+            let quad = Quad::new(to_vec3(quad.corner), u, v, self.lookup(materials, &quad.material, "material")?);
+            
+            if quad.material.is_emissive() {
+                lights.add(Box::new(quad.clone()));
+            }
+            world.add(Box::new(quad));
+        }
+        
+        for box_desc in geometry.boxes.iter() {
+            let (min, max) = (to_vec3(box_desc.min), to_vec3(*box_desc.max.get_ref()));
+            if min.x == max.x || min.y == max.y || min.z == max.z {
+                return Err(self.error(box_desc.max.span(), String::from("box corners must differ on every axis")));
+            }
+            
+            let material = self.lookup(materials, &box_desc.material, "material")?;
+            let is_emissive = material.is_emissive();
+            
+            for side in box_quads(&min, &max, material) {
+                if is_emissive {
+                    lights.add(Box::new(side.clone()));
+                }
+                world.add(Box::new(side));
+            }
+        }
+        
+        for disk in geometry.disks.iter() {
+            let disk = Disk {
+                center: to_vec3(disk.center),
+                normal: self.normal(&disk.normal, "disk")?,
+                radius: self.positive(&disk.radius, "disk radius")?,
+                material: self.lookup(materials, &disk.material, "material")?
+            };
+            
+            if disk.material.is_emissive() {
+                lights.add(Box::new(disk.clone()));
+            }
+            world.add(Box::new(disk));
+        }
+        
+        // Planes are never sampled as lights, they have no finite area to pick points on
+        for plane in geometry.planes.iter() {
+            world.add(Box::new(Plane {
+                point: to_vec3(plane.point),
+                normal: self.normal(&plane.normal, "plane")?,
+                material: self.lookup(materials, &plane.material, "material")?
+            }));
+        }
+        
+        for mesh in geometry.meshes.iter() {
+            let default_material = match &mesh.material {
+                Some(name) => self.lookup(materials, name, "material")?,
+                // Same gray as an MTL entry without a Kd
+                None => Arc::new(Lambertian::new(Color01::new(0.8, 0.8, 0.8)))
+            };
+            
+            for triangle_mesh in obj::load_obj(&self.resolve_path(&mesh.path), default_material)? {
+                if triangle_mesh.material().is_emissive() {
+                    for triangle in triangle_mesh.triangles() {
+                        lights.add(Box::new(triangle));
+                    }
+                }
+                world.add(Box::new(triangle_mesh));
+            }
+        }
+        
+        Ok((world, lights))
+    }
+    
+    fn build_volume(&self, desc: &VolumeDesc, materials: &HashMap<&str, Arc<dyn Material>>) -> Result<GridMedium, SceneError> {
+        let min = to_vec3(desc.min);
+        let max = to_vec3(*desc.max.get_ref());
+        if max.x <= min.x || max.y <= min.y || max.z <= min.z {
+            return Err(self.error(desc.max.span(), String::from("volume max must be above min on every axis")));
+        }
+        
+        let density_scale = *desc.density.get_ref();
+        if density_scale.is_nan() || density_scale < 0.0 {
+            return Err(self.error(desc.density.span(), format!("volume density can't be negative, got {}", density_scale)));
+        }
+        
+        let phase = self.lookup(materials, &desc.material, "material")?;
+        let density = match &desc.grid {
+            Some(relative) => self.load_grid(relative)?,
+            None => {
+                let resolution = match &desc.resolution {
+                    Some(resolution) => {
+                        // Keeps the voxel count within a few GiB of memory
+                        if !(1..=1024).contains(resolution.get_ref()) {
+                            return Err(self.error(resolution.span(), format!("noise resolution must be between 1 and 1024, got {}", resolution.get_ref())));
+                        }
+                        *resolution.get_ref()
+                    },
+                    None => 64
+                };
+                
+                Arc::new(VoxelGrid::noise_cloud(resolution, desc.frequency.unwrap_or(4.0)))
+            }
+        };
+        
+        let emission = match &desc.emission {
+            Some(emission) => {
+                // Densities are normalized so the emission scale doesn't depend on the grid's units
+                let max_density = density.max_value().max(1e-12);
+                let normalized = || Arc::new(density.map(|value| value / max_density));
+                
+                let intensity = match &emission.grid {
+                    Some(relative) => self.load_grid(relative)?,
+                    None => normalized()
+                };
+                let temperature = match (&emission.temperature_grid, emission.temperature) {
+                    (Some(name), Some(_)) => return Err(self.error(name.span(), String::from("set either `temperature_grid` or `temperature`, not both"))),
+                    (Some(relative), None) => Some(self.load_grid(relative.get_ref())?),
+                    (None, Some([low, high])) => Some(Arc::new(density.map(|value| low + (high - low) * value / max_density))),
+                    (None, None) => None
+                };
+                
+                Some(VolumeEmission {
+                    intensity,
+                    temperature,
+                    color: emission.color.map_or(Color01::new(1.0, 1.0, 1.0), to_color),
+                    scale: emission.scale.unwrap_or(1.0)
+                })
+            },
+            None => None
+        };
+        
+        Ok(GridMedium::new(Aabb::new(min, max), density, density_scale, phase, emission))
+    }
+    
+    fn load_grid(&self, relative: &str) -> Result<Arc<VoxelGrid>, SceneError> {
+        let path = self.resolve_path(relative);
+        let grid = VoxelGrid::load_vol(&path).map_err(|error| SceneError::Io {path, error})?;
+        
+        Ok(Arc::new(grid))
+    }
+    
+    fn positive(&self, value: &Spanned<f64>, what: &str) -> Result<f64, SceneError> {
+        let number = *value.get_ref();
+        if number.is_nan() || number <= 0.0 {
+            return Err(self.error(value.span(), format!("{} must be positive, got {}", what, number)));
+        }
+        
+        Ok(number)
+    }
+    
+    // Henyey-Greenstein turns into a delta distribution at -1 and 1
+    fn anisotropy(&self, anisotropy: f64, span: Range<usize>) -> Result<f64, SceneError> {
+        if !(anisotropy > -1.0 && anisotropy < 1.0) {
+            return Err(self.error(span, format!("anisotropy must be strictly between -1 and 1, got {}", anisotropy)));
+        }
+        
+        Ok(anisotropy)
+    }
+    
+    fn normal(&self, normal: &Spanned<[f64; 3]>, shape: &str) -> Result<Vec3, SceneError> {
+        let vector = to_vec3(*normal.get_ref());
+        if vector.len_sqr() == 0.0 {
+            return Err(self.error(normal.span(), format!("{} normal can't be zero", shape)));
+        }
+        
+        Ok(vector)
+    }
+    
+    fn lookup<T: Clone>(&self, table: &HashMap<&str, T>, name: &Spanned<String>, kind: &str) -> Result<T, SceneError> {
+        self.lookup_at(table, name.get_ref(), name.span(), kind)
+    }
+    
+    // For names that lost their own span, the error points at span instead
+    fn lookup_at<T: Clone>(&self, table: &HashMap<&str, T>, name: &str, span: Range<usize>, kind: &str) -> Result<T, SceneError> {
+        match table.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => Err(self.error(span, format!("unknown {} '{}'", kind, name)))
+        }
+    }
+    
+    fn build_camera(&self, desc: &CameraDesc) -> Result<Camera, SceneError> {
+        let mut camera = Camera::default();
+        
+        if let Some(position) = desc.position {
+            camera.position = to_vec3(position);
+        }
+        if let Some(look_at) = desc.look_at {
+            camera.look_at = to_vec3(look_at);
+        }
+        if let Some(up_vector) = desc.up_vector {
+            camera.up_vector = to_vec3(up_vector);
+        }
+        if let Some(fov_vertical) = desc.fov_vertical {
+            camera.fov_vertical = fov_vertical;
+        }
+        if let Some(defocus_angle) = desc.defocus_angle {
+            camera.defocus_angle = defocus_angle;
+        }
+        if let Some(focus_distance) = desc.focus_distance {
+            camera.focus_distance = focus_distance;
+        }
+        if let Some(shutter_open) = desc.shutter_open {
+            camera.shutter_open = shutter_open;
+        }
+        if let Some(shutter_close) = desc.shutter_close {
+            camera.shutter_close = shutter_close;
+        }
+        if let Some(aspect_ratio) = desc.aspect_ratio {
+            camera.aspect_ratio = aspect_ratio;
+        }
+        if let Some(frame_width) = desc.frame_width {
+            camera.frame_width = frame_width;
+        }
+        if let Some(samples_per_pixel) = desc.samples_per_pixel {
+            camera.samples_per_pixel = samples_per_pixel;
+        }
+        if let Some(ray_bounces_max) = desc.ray_bounces_max {
+            camera.ray_bounces_max = ray_bounces_max;
+        }
+        if let Some(roulette_depth) = desc.roulette_depth {
+            camera.roulette_depth = roulette_depth;
+        }
+        if let Some(seed) = desc.seed {
+            camera.seed = seed;
+        }
+        if let Some(light_sampling) = &desc.light_sampling {
+            camera.light_sampling = light_sampling.get_ref().parse().map_err(|message| self.error(light_sampling.span(), message))?;
+        }
+        if let Some(spectral) = desc.spectral {
+            camera.spectral = spectral;
+        }
+        
+        if let Some(background) = &desc.background {
+            camera.background = self.build_background(background)?;
+        }
+        
+        if let Some(atmosphere) = &desc.atmosphere {
+            camera.atmosphere = Some(Atmosphere {
+                density: self.positive(&atmosphere.density, "atmosphere density")?,
+                albedo: atmosphere.albedo.map_or(Color01::new(1.0, 1.0, 1.0), to_color),
+                anisotropy: match &atmosphere.anisotropy {
+                    Some(anisotropy) => self.anisotropy(*anisotropy.get_ref(), anisotropy.span())?,
+                    None => 0.0
+                },
+                radius: match &atmosphere.radius {
+                    Some(radius) => self.positive(radius, "atmosphere radius")?,
+                    None => 100.0
+                }
+            });
+        }
+        
+        Ok(camera)
+    }
+    
+    fn build_background(&self, desc: &BackgroundDesc) -> Result<Background, SceneError> {
+        let background = match desc.type_name.get_ref().as_str() {
+            "black" => Background::Black,
+            "solid" => Background::Solid(to_color(self.required(desc.color, &desc.type_name, "color")?)),
+            "gradient" => Background::Gradient {
+                bottom: to_color(self.required(desc.bottom, &desc.type_name, "bottom")?),
+                top: to_color(self.required(desc.top, &desc.type_name, "top")?)
+            },
+            "environment" => {
+                let path = self.resolve_path(self.required(desc.path.as_ref(), &desc.type_name, "path")?);
+                let image = Image::load_hdr(&path).map_err(|error| SceneError::Io {path, error})?;
+                if image.width == 0 || image.height == 0 {
+                    return Err(self.error(desc.type_name.span(), String::from("environment map image is empty")));
+                }
+                
+                Background::Environment(Arc::new(EnvironmentMap::new(image, desc.rotation.unwrap_or(0.0), desc.intensity.unwrap_or(1.0))))
+            },
+            "sky" => {
+                let elevation = self.required(desc.elevation, &desc.type_name, "elevation")?;
+                if !(0.0..=90.0).contains(&elevation) {
+                    return Err(self.error(desc.type_name.span(), format!("sun elevation must be between 0 and 90 degrees, got {}", elevation)));
+                }
+                
+                // The range the Preetham model was fitted for
+                let turbidity = desc.turbidity.unwrap_or(3.0);
+                if !(1.7..=10.0).contains(&turbidity) {
+                    return Err(self.error(desc.type_name.span(), format!("turbidity must be between 1.7 and 10, got {}", turbidity)));
+                }
+                
+                let sun_radius = desc.sun_radius.unwrap_or(0.2665);
+                if sun_radius <= 0.0 || sun_radius >= 90.0 {
+                    return Err(self.error(desc.type_name.span(), format!("sun radius must be between 0 and 90 degrees, got {}", sun_radius)));
+                }
+                
+                // Brings the sky's kcd/m^2 down to about the brightness of the other backgrounds
+                let intensity = desc.intensity.unwrap_or(0.03);
+                
+                Background::Sky(Arc::new(SunSky::new(elevation, desc.azimuth.unwrap_or(0.0), turbidity, sun_radius, intensity)))
+            },
+            other => return Err(self.error(desc.type_name.span(), format!("unknown background type '{}', expected black, solid, gradient, environment or sky", other)))
+        };
+        
+        Ok(background)
+    }
+    
+    fn build_light(&self, desc: &LightDesc) -> Result<DeltaLight, SceneError> {
+        let intensity = to_color(desc.color.unwrap_or([1.0, 1.0, 1.0])) * desc.intensity.unwrap_or(1.0);
+        let direction = || -> Result<Vec3, SceneError> {
+            let direction = to_vec3(self.required(desc.direction, &desc.type_name, "direction")?);
+            if direction.len() <= 0.0 {
+                return Err(self.error(desc.type_name.span(), String::from("light direction can't be zero")));
+            }
+            
+            Ok(direction)
+        };
+        
+        let light = match desc.type_name.get_ref().as_str() {
+            "point" => DeltaLight::Point {
+                position: to_vec3(self.required(desc.position, &desc.type_name, "position")?),
+                intensity
+            },
+            "spot" => {
+                let position = to_vec3(self.required(desc.position, &desc.type_name, "position")?);
+                let cone_angle = desc.cone_angle.unwrap_or(30.0);
+                if cone_angle <= 0.0 || cone_angle > 180.0 {
+                    return Err(self.error(desc.type_name.span(), format!("spot light cone angle must be between 0 and 180 degrees, got {}", cone_angle)));
+                }
+                
+                DeltaLight::spot(position, direction()?, intensity, cone_angle, desc.falloff_angle.unwrap_or(5.0))
+            },
+            "directional" => DeltaLight::Directional {direction: direction()?.normalized(), irradiance: intensity},
+            other => return Err(self.error(desc.type_name.span(), format!("unknown light type '{}', expected point, spot or directional", other)))
+        };
+        
+        Ok(light)
+    }
+    
+    fn build_output(&self, desc: &OutputDesc) -> Result<OutputSettings, SceneError> {
+        let path = self.resolve_path(desc.path.as_deref().unwrap_or("render.png"));
+        
+        let format = match &desc.format {
+            Some(format) => format.get_ref().parse().map_err(|message| self.error(format.span(), message))?,
+            None => ImageFormat::from_extension(&path).unwrap_or(ImageFormat::Png)
+        };
+        
+        Ok(OutputSettings {path, format})
+    }
+    
+    fn build_texture(&self, desc: &TextureDesc) -> Result<Arc<dyn Texture>, SceneError> {
+        let texture: Arc<dyn Texture> = match desc.type_name.get_ref().as_str() {
+            "solid" => Arc::new(to_color(self.required(desc.color, &desc.type_name, "color")?)),
+            "checker" => Arc::new(CheckerTexture {
+                scale: desc.scale.unwrap_or(1.0),
+                even: Arc::new(to_color(self.required(desc.even, &desc.type_name, "even")?)),
+                odd: Arc::new(to_color(self.required(desc.odd, &desc.type_name, "odd")?))
+            }),
+            "image" => {
+                let relative = self.required(desc.path.as_ref(), &desc.type_name, "path")?;
+                let wrap = match &desc.wrap {
+                    Some(wrap) => match wrap.get_ref().as_str() {
+                        "repeat" => WrapMode::Repeat,
+                        "mirror" => WrapMode::Mirror,
+                        "clamp" => WrapMode::Clamp,
+                        other => return Err(self.error(wrap.span(), format!("unknown wrap mode '{}', expected repeat, mirror or clamp", other)))
+                    },
+                    None => WrapMode::Repeat
+                };
+                
+                let path = self.resolve_path(relative);
+                let texture = ImageTexture::load(&path, wrap).map_err(|error| SceneError::Io {path, error})?;
+                
+                Arc::new(texture)
+            },
+            "noise" => {
+                let kind = match &desc.kind {
+                    Some(kind) => match kind.get_ref().as_str() {
+                        "smooth" => NoiseKind::Smooth,
+                        "turbulence" => NoiseKind::Turbulence,
+                        "marble" => NoiseKind::Marble,
+                        other => return Err(self.error(kind.span(), format!("unknown noise kind '{}', expected smooth, turbulence or marble", other)))
+                    },
+                    None => NoiseKind::Marble
+                };
+                let color = desc.color.map_or(Color01::new(1.0, 1.0, 1.0), to_color);
+                
+                Arc::new(NoiseTexture::new(desc.scale.unwrap_or(1.0), kind, color))
+            },
+            other => return Err(self.error(desc.type_name.span(), format!("unknown texture type '{}', expected solid, checker, image or noise", other)))
+        };
+        
+        Ok(texture)
+    }
+    
+    fn build_material(&self, desc: &Spanned<MaterialDesc>, textures: &HashMap<&str, Arc<dyn Texture>>) -> Result<Arc<dyn Material>, SceneError> {
+        let span = desc.span();
+        // A texture replaces the flat albedo when set
+        let albedo = |color: &Option<[f64; 3]>, texture: &Option<String>| -> Result<Arc<dyn Texture>, SceneError> {
+            match texture {
+                Some(name) => self.lookup_at(textures, name, span.clone(), "texture"),
+                None => Ok(Arc::new(color.map_or(Color01::new(1.0, 1.0, 1.0), to_color)))
+            }
+        };
+        
+        let material: Arc<dyn Material> = match desc.get_ref() {
+            MaterialDesc::Lambert {albedo: color, texture} => Arc::new(Lambertian {albedo: albedo(color, texture)?}),
+            MaterialDesc::Metal {albedo: color, texture, fuzziness} => Arc::new(Metal {albedo: albedo(color, texture)?, fuzziness: fuzziness.unwrap_or(0.0)}),
+            MaterialDesc::Dielectric {refraction_idx, dispersion} => match dispersion {
+                Some(dispersion) => {
+                    if refraction_idx.is_some() {
+                        return Err(self.error(span, String::from("dielectric can't set both `refraction_idx` and `dispersion`")));
+                    }
+                    
+                    Arc::new(Dielectric::dispersive(self.build_dispersion(dispersion, span)?))
+                },
+                None => Arc::new(Dielectric::new(refraction_idx.unwrap_or(1.5)))
+            },
+            MaterialDesc::Conductor {eta, k, preset, roughness} => {
+                let roughness = roughness.unwrap_or(0.0);
+                
+                match (preset, eta, k) {
+                    (Some(preset), None, None) => Arc::new(Conductor::measured(self.conductor_preset(preset, span)?, roughness)),
+                    (None, Some(eta), Some(k)) => Arc::new(Conductor::new(to_color(*eta), to_color(*k), roughness)),
+                    _ => return Err(self.error(span, String::from("conductor needs either a `preset` or both `eta` and `k`")))
+                }
+            },
+            MaterialDesc::RoughDielectric {refraction_idx, roughness} => Arc::new(RoughDielectric::new(refraction_idx.unwrap_or(1.5), roughness.unwrap_or(0.0))),
+            MaterialDesc::Principled(principled) => {
+                let base_color = albedo(&principled.albedo, &principled.texture)?;
+                
+                Arc::new(self.build_principled(principled, span, base_color, textures)?)
+            },
+            MaterialDesc::Isotropic {albedo: color, texture} => Arc::new(Isotropic {albedo: albedo(color, texture)?}),
+            MaterialDesc::HenyeyGreenstein {albedo: color, texture, anisotropy} => Arc::new(HenyeyGreenstein {
+                albedo: albedo(color, texture)?,
+                anisotropy: self.anisotropy(anisotropy.unwrap_or(0.0), span)?
+            }),
+            MaterialDesc::DiffuseLight {albedo: color, texture, intensity} => Arc::new(DiffuseLight {emit: albedo(color, texture)?, intensity: intensity.unwrap_or(1.0)})
+        };
+        
+        Ok(material)
+    }
+    
+    fn conductor_preset(&self, name: &str, span: Range<usize>) -> Result<&'static ComplexIor, SceneError> {
+        match name {
+            "gold" => Ok(&GOLD),
+            "silver" => Ok(&SILVER),
+            "copper" => Ok(&COPPER),
+            "aluminium" => Ok(&ALUMINIUM),
+            other => Err(self.error(span, format!("unknown conductor preset '{}', expected gold, silver, copper or aluminium", other)))
+        }
+    }
+    
+    fn build_dispersion(&self, desc: &DispersionDesc, span: Range<usize>) -> Result<Dispersion, SceneError> {
+        let dispersion = match *desc {
+            DispersionDesc::Cauchy {a, b} => Dispersion::Cauchy {a, b},
+            DispersionDesc::Sellmeier {b, c} => Dispersion::Sellmeier {b, c}
+        };
+        
+        // Light can't refract through an index below one, or one that isn't a number
+        for wavelength in [WAVELENGTH_MIN, D_LINE, WAVELENGTH_MAX] {
+            let refraction_idx = dispersion.refraction_idx(wavelength);
+            if !(refraction_idx >= 1.0 && refraction_idx.is_finite()) {
+                return Err(self.error(span, format!("dispersion gives an index of refraction of {} at {}nm", refraction_idx, wavelength)));
+            }
+        }
+        
+        Ok(dispersion)
+    }
+    
+    fn build_principled(&self, desc: &PrincipledDesc, span: Range<usize>, base_color: Arc<dyn Texture>, textures: &HashMap<&str, Arc<dyn Texture>>) -> Result<Principled, SceneError> {
+        let maps = match &desc.maps {
+            Some(maps) => maps,
+            None => &PrincipledMapsDesc::default()
+        };
+        
+        let parameter = |map: &Option<String>, value: Option<f64>, default: f64| -> Result<Arc<dyn Texture>, SceneError> {
+            match map {
+                Some(name) => self.lookup_at(textures, name, span.clone(), "texture"),
+                None => Ok(texture::constant(value.unwrap_or(default)))
+            }
+        };
+        
+        Ok(Principled {
+            base_color,
+            metallic: parameter(&maps.metallic, desc.metallic, 0.0)?,
+            roughness: parameter(&maps.roughness, desc.roughness, 0.5)?,
+            specular: parameter(&maps.specular, desc.specular, 0.5)?,
+            specular_tint: parameter(&maps.specular_tint, desc.specular_tint, 0.0)?,
+            sheen: parameter(&maps.sheen, desc.sheen, 0.0)?,
+            sheen_tint: parameter(&maps.sheen_tint, desc.sheen_tint, 0.5)?,
+            clearcoat: parameter(&maps.clearcoat, desc.clearcoat, 0.0)?,
+            clearcoat_gloss: parameter(&maps.clearcoat_gloss, desc.clearcoat_gloss, 1.0)?,
+            transmission: parameter(&maps.transmission, desc.transmission, 0.0)?,
+            refraction_idx: parameter(&maps.refraction_idx, desc.refraction_idx, 1.5)?
+        })
+    }
+    
+    // Reports a field that only some types need at the location of the type that needs it
+    fn required<T>(&self, value: Option<T>, type_name: &Spanned<String>, field: &str) -> Result<T, SceneError> {
+        value.ok_or_else(|| self.error(type_name.span(), format!("type '{}' requires field `{}`", type_name.get_ref(), field)))
+    }
+}
+
+fn instance_transform(desc: &InstanceDesc) -> Mat4 {
+    let scale = Mat4::scaling(&to_vec3(desc.scale.unwrap_or([1.0, 1.0, 1.0])));
+    let [x, y, z] = desc.rotate.unwrap_or([0.0, 0.0, 0.0]);
+    let rotate = Mat4::rotation(&Vec3::new(0.0, 0.0, 1.0), z) * Mat4::rotation(&Vec3::new(0.0, 1.0, 0.0), y) * Mat4::rotation(&Vec3::new(1.0, 0.0, 0.0), x);
+    let translate = Mat4::translation(&to_vec3(desc.translate.unwrap_or([0.0, 0.0, 0.0])));
+    
+    translate * rotate * scale
+}
+
+fn to_vec3(value: [f64; 3]) -> Vec3 {
+    Vec3::new(value[0], value[1], value[2])
+}
+
+fn to_color(value: [f64; 3]) -> Color01 {
+    Color01::new(value[0], value[1], value[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn parse_error(source: &str) -> (usize, usize, String) {
+        match parse_scene(Path::new("test.toml"), source, None) {
+            Err(SceneError::Parse {line, column, message, ..}) => (line, column, message),
+            Err(error) => panic!("expected a parse error, got {}", error),
+            Ok(_) => panic!("expected a parse error")
+        }
+    }
+    
+    #[test]
+    fn unknown_key_error_points_at_the_key() {
+        let (line, column, message) = parse_error("[camera]\nsamples_per_pixel = 4\nfocal_lenght = 2.0\n");
+        assert_eq!((line, column), (3, 1));
+        assert!(message.contains("unknown field `focal_lenght`"), "{}", message);
+    }
+    
+    #[test]
+    fn material_errors_point_at_the_material() {
+        let (line, _, message) = parse_error("[camera]\n\n[materials.a]\ntype = \"lambert\"\nfuzziness = 0.3\n");
+        assert_eq!(line, 3);
+        assert!(message.contains("unknown field `fuzziness`"), "{}", message);
+        
+        let (line, _, message) = parse_error("[materials.a]\ntype = \"lambrt\"\n");
+        assert_eq!(line, 2);
+        assert!(message.contains("unknown variant `lambrt`"), "{}", message);
+        
+        let (line, _, message) = parse_error("[camera]\n\n[materials.a]\ntype = \"metal\"\ntexture = \"missing\"\n");
+        assert_eq!(line, 3);
+        assert!(message.contains("unknown texture 'missing'"), "{}", message);
+    }
+    
+    #[test]
+    fn degenerate_flat_shapes_are_rejected() {
+        // The material table takes the first three lines, the shape table starts on line four
+        let cases = [
+            ("[[quads]]\ncorner = [0, 0, 0]\nu = [1, 1, 0]\nv = [2, 2, 0]\nmaterial = \"m\"\n", 7, "not parallel"),
+            ("[[quads]]\ncorner = [0, 0, 0]\nu = [1, 0, 0]\nv = [0, 0, 0]\nmaterial = \"m\"\n", 7, "not parallel"),
+            ("[[boxes]]\nmin = [0, 0, 0]\nmax = [1, 0, 1]\nmaterial = \"m\"\n", 6, "differ on every axis"),
+            ("[[disks]]\ncenter = [0, 0, 0]\nnormal = [0, 0, 0]\nradius = 1.0\nmaterial = \"m\"\n", 6, "disk normal can't be zero"),
+            ("[[disks]]\ncenter = [0, 0, 0]\nnormal = [0, 1, 0]\nradius = 0.0\nmaterial = \"m\"\n", 7, "radius must be positive"),
+            ("[[planes]]\npoint = [0, 0, 0]\nnormal = [0, 0, 0]\nmaterial = \"m\"\n", 6, "plane normal can't be zero")
+        ];
+        
+        for (shape, expected_line, expected) in cases {
+            let (line, _, message) = parse_error(&format!("[materials.m]\ntype = \"lambert\"\n\n{}", shape));
+            assert!(message.contains(expected), "{}", message);
+            assert_eq!(line, expected_line, "{}", message);
+        }
+    }
+    
+    #[test]
+    fn media_need_a_positive_density_and_valid_anisotropy() {
+        let (line, _, message) = parse_error("[objects.blob]\nspheres = [{center = [0, 0, 0], radius = 1.0, material = \"m\"}]\n\n[materials.m]\ntype = \"isotropic\"\n\n[[media]]\nobject = \"blob\"\ndensity = 0.0\nmaterial = \"m\"\n");
+        assert_eq!(line, 9);
+        assert!(message.contains("medium density must be positive"), "{}", message);
+        
+        let (line, _, message) = parse_error("[camera]\n\n[materials.m]\ntype = \"henyey_greenstein\"\nanisotropy = 1.0\n");
+        assert_eq!(line, 3);
+        assert!(message.contains("anisotropy must be strictly between -1 and 1"), "{}", message);
+        
+        let (line, _, message) = parse_error("[camera.atmosphere]\ndensity = -0.1\n");
+        assert_eq!(line, 2);
+        assert!(message.contains("atmosphere density must be positive"), "{}", message);
+        
+        let (line, _, message) = parse_error("[camera.atmosphere]\ndensity = 0.1\nanisotropy = -1.5\n");
+        assert_eq!(line, 3);
+        assert!(message.contains("anisotropy must be strictly between -1 and 1"), "{}", message);
+    }
+    
+    #[test]
+    fn volume_density_and_resolution_are_checked() {
+        let volume = "[materials.m]\ntype = \"isotropic\"\n\n[[volumes]]\nmin = [0, 0, 0]\nmax = [1, 1, 1]\nmaterial = \"m\"\n";
+        
+        let (line, _, message) = parse_error(&format!("{}density = -1.0\n", volume));
+        assert_eq!(line, 8);
+        assert!(message.contains("volume density can't be negative"), "{}", message);
+        
+        let (line, _, message) = parse_error(&format!("{}density = 1.0\nresolution = 0\n", volume));
+        assert_eq!(line, 9);
+        assert!(message.contains("noise resolution must be between 1 and 1024"), "{}", message);
+    }
+}
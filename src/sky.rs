@@ -0,0 +1,141 @@
+use crate::color::Color01;
+use crate::rt_util;
+use crate::vector::*;
+
+// Solar illuminance outside the atmosphere in klux. It's spread over the sun's disk, so a larger sun
+// gives softer shadows without brightening the scene
+const SUN_ILLUMINANCE: f64 = 128.0;
+
+// Share of light samples aimed at the sun, the rest follow the cosine around the zenith
+const SUN_CHANCE: f64 = 0.5;
+
+// Wavelengths in micrometers the sun's transmittance is evaluated at for each channel
+const CHANNEL_WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
+
+// Preetham et al. (1999) daylight. The sky's luminance and chromaticity follow Perez distributions
+// fitted for the sun position and turbidity, the haziness of the air from 2 (clear) to 10 (hazy), and
+// the sun is a disk dimmed and reddened by the air it shines through. Radiance is in kcd/m^2 times
+// `intensity`. Below the horizon is black, outdoor scenes need a ground
+#[derive(Debug)]
+pub struct SunSky {
+    sun_direction: Vec3,
+    // Cosine of the sun's angular radius
+    sun_cos_max: f64,
+    sun_radiance: Color01,
+    // Perez coefficients A to E for the luminance and the x and y chromaticity
+    perez: [[f64; 5]; 3],
+    // Zenith luminance and chromaticity over the Perez function at the zenith
+    zenith: [f64; 3],
+    intensity: f64
+}
+
+impl SunSky {
+    // Angles in degrees, an azimuth of 0 puts the sun towards -z and 90 towards +x
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, sun_radius: f64, intensity: f64) -> SunSky {
+        let elevation = rt_util::deg_to_rad(elevation.clamp(0.0, 90.0));
+        let azimuth = rt_util::deg_to_rad(azimuth);
+        let sun_direction = Vec3::new(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos());
+        
+        let t = turbidity;
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529]
+        ];
+        
+        // Zenith values for the sun's angle from the zenith
+        let theta_s = rt_util::PI * 0.5 - elevation;
+        let (theta_2, theta_3) = (theta_s * theta_s, theta_s * theta_s * theta_s);
+        let chi = (4.0 / 9.0 - t / 120.0) * (rt_util::PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * (0.00166 * theta_3 - 0.00375 * theta_2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta_3 + 0.06377 * theta_2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta_3 - 0.21196 * theta_2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta_3 - 0.00610 * theta_2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta_3 + 0.08970 * theta_2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta_3 - 0.26756 * theta_2 + 0.06670 * theta_s + 0.26688);
+        
+        let zenith_values = [zenith_luminance, zenith_x, zenith_y];
+        let zenith = [0, 1, 2].map(|i| zenith_values[i] / perez_function(&perez[i], 1.0, theta_s));
+        
+        let sun_cos_max = rt_util::deg_to_rad(sun_radius).cos();
+        let sun_solid_angle = 2.0 * rt_util::PI * (1.0 - sun_cos_max);
+        
+        SunSky {
+            sun_direction,
+            sun_cos_max,
+            sun_radiance: sun_transmittance(theta_s, turbidity) * Color01::blackbody(5778.0) * (SUN_ILLUMINANCE / sun_solid_angle),
+            perez,
+            zenith,
+            intensity
+        }
+    }
+    
+    pub fn radiance(&self, direction: &Vec3) -> Color01 {
+        let direction = direction.normalized();
+        if direction.y <= 0.0 {
+            return Color01::default();
+        }
+        
+        let cos_gamma = dot(&direction, &self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| self.zenith[i] * perez_function(&self.perez[i], direction.y, gamma));
+        
+        let sky = Color01::from_xyz(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        let mut radiance = Color01::new(sky.r.max(0.0), sky.g.max(0.0), sky.b.max(0.0));
+        if cos_gamma >= self.sun_cos_max {
+            radiance += self.sun_radiance;
+        }
+        
+        radiance * self.intensity
+    }
+    
+    pub fn sample_direction(&self) -> Vec3 {
+        if rt_util::random() < SUN_CHANCE {
+            let z = 1.0 + rt_util::random() * (self.sun_cos_max - 1.0);
+            let phi = 2.0 * rt_util::PI * rt_util::random();
+            let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+            
+            return Onb::new(&self.sun_direction).local_to_world(&Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z));
+        }
+        
+        Vec3::new(0.0, 1.0, 0.0) + rt_util::random_on_unit_sphere()
+    }
+    
+    pub fn pdf(&self, direction: &Vec3) -> f64 {
+        let direction = direction.normalized();
+        let sun_pdf = if dot(&direction, &self.sun_direction) >= self.sun_cos_max {
+            1.0 / (2.0 * rt_util::PI * (1.0 - self.sun_cos_max))
+        }
+        else {
+            0.0
+        };
+        
+        SUN_CHANCE * sun_pdf + (1.0 - SUN_CHANCE) * direction.y.max(0.0) / rt_util::PI
+    }
+}
+
+// Perez et al. sky distribution for a direction at `cos_theta` from the zenith and `gamma` radians
+// from the sun
+fn perez_function(coefficients: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coefficients;
+    
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+}
+
+// Rayleigh and aerosol extinction of sunlight along the air mass for the sun's zenith angle, the
+// relative air mass is from Kasten and Young (1989)
+fn sun_transmittance(theta_s: f64, turbidity: f64) -> Color01 {
+    let air_mass = 1.0 / (theta_s.cos() + 0.50572 * (96.07995 - rt_util::rad_to_deg(theta_s)).powf(-1.6364));
+    // Angstrom turbidity coefficient, with a wavelength exponent of 1.3
+    let beta = 0.04608 * turbidity - 0.04586;
+    
+    let [r, g, b] = CHANNEL_WAVELENGTHS.map(|lambda: f64| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+        let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+        
+        rayleigh * aerosol
+    });
+    
+    Color01::new(r, g, b)
+}
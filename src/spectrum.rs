@@ -0,0 +1,270 @@
+use std::sync::OnceLock;
+
+use crate::color::Color01;
+use crate::rt_util;
+
+// Visible range wavelengths are drawn from in nanometers, the span of Smits' basis spectra
+pub const WAVELENGTH_MIN: f64 = 380.0;
+pub const WAVELENGTH_MAX: f64 = 720.0;
+
+// Sodium d-line in nanometers, where the index of dispersive glass is taken for RGB renders
+pub const D_LINE: f64 = 587.6;
+
+// Smits (1999) reflectance spectra, ten equal bins over the visible range. An RGB color is the sum of
+// white, one secondary and one primary spectrum
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// Hero wavelength sampling (Wilkie et al. 2014), a path carries its randomly drawn hero wavelength and
+// two more spread evenly over the visible range from it. Path values stay Color01, with the r, g and b
+// channels holding the values at the hero and the two other wavelengths
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wavelengths {
+    lambda: [f64; 3]
+}
+
+impl Wavelengths {
+    pub fn sample_hero() -> f64 {
+        WAVELENGTH_MIN + rt_util::random() * (WAVELENGTH_MAX - WAVELENGTH_MIN)
+    }
+    
+    pub fn from_hero(hero: f64) -> Wavelengths {
+        let range = WAVELENGTH_MAX - WAVELENGTH_MIN;
+        let lambda = [0.0, 1.0, 2.0].map(|i| WAVELENGTH_MIN + (hero - WAVELENGTH_MIN + i * range / 3.0) % range);
+        
+        Wavelengths {lambda}
+    }
+    
+    // Values of the Smits spectrum of an RGB color at the three wavelengths. Reflectances stay within
+    // [0, 1] and emission is upsampled the same way, scaled by its brightness
+    pub fn upsample(&self, color: Color01) -> Color01 {
+        let Color01 {r, g, b} = color;
+        
+        // The smallest channel is white, what the middle one has over it is the secondary color the two
+        // larger channels share and the rest is the primary of the largest channel
+        let (white, secondary, secondary_amount, primary, primary_amount) = if r <= g && r <= b {
+            if g <= b { (r, &SMITS_CYAN, g - r, &SMITS_BLUE, b - g) } else { (r, &SMITS_CYAN, b - r, &SMITS_GREEN, g - b) }
+        }
+        else if g <= r && g <= b {
+            if r <= b { (g, &SMITS_MAGENTA, r - g, &SMITS_BLUE, b - r) } else { (g, &SMITS_MAGENTA, b - g, &SMITS_RED, r - b) }
+        }
+        else if r <= g {
+            (b, &SMITS_YELLOW, r - b, &SMITS_GREEN, g - r)
+        }
+        else {
+            (b, &SMITS_YELLOW, g - b, &SMITS_RED, r - g)
+        };
+        
+        let [hero, second, third] = self.lambda.map(|lambda| {
+            let bin = (((lambda - WAVELENGTH_MIN) / (WAVELENGTH_MAX - WAVELENGTH_MIN) * 10.0) as usize).min(9);
+            
+            white * SMITS_WHITE[bin] + secondary_amount * secondary[bin] + primary_amount * primary[bin]
+        });
+        
+        Color01::new(hero, second, third)
+    }
+    
+    // Estimates CIE XYZ from the values at the three uniformly drawn wavelengths and converts it to linear
+    // sRGB. The result is white balanced per channel so that a constant spectrum comes out white, like a
+    // white color does in RGB renders
+    pub fn to_rgb(self, values: Color01) -> Color01 {
+        let normalization = normalization();
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for (value, lambda) in [values.r, values.g, values.b].into_iter().zip(self.lambda) {
+            let [x_bar, y_bar, z_bar] = matching_functions(lambda);
+            x += value * x_bar;
+            y += value * y_bar;
+            z += value * z_bar;
+        }
+        
+        let scale = (WAVELENGTH_MAX - WAVELENGTH_MIN) / (3.0 * normalization.luminance);
+        let color = Color01::from_xyz(x * scale, y * scale, z * scale);
+        let white = normalization.white;
+        
+        Color01::new(color.r / white.r, color.g / white.g, color.b / white.b)
+    }
+    
+    // Drops the two secondary wavelengths once the path has taken a direction only the hero wavelength
+    // would take, the hero's value is scaled up to stand in for all three
+    pub fn hero_only(values: Color01) -> Color01 {
+        Color01::new(values.r * 3.0, 0.0, 0.0)
+    }
+}
+
+// Wavelength dependent index of refraction of a dielectric, the coefficients are for wavelengths in
+// micrometers as glass catalogs list them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispersion {
+    // n = a + b / λ^2
+    Cauchy {
+        a: f64,
+        b: f64
+    },
+    // n^2 = 1 + Σ b_i λ^2 / (λ^2 - c_i)
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3]
+    }
+}
+
+impl Dispersion {
+    // Wavelength in nanometers
+    pub fn refraction_idx(&self, wavelength: f64) -> f64 {
+        let lambda_2 = (wavelength * 0.001) * (wavelength * 0.001);
+        
+        match self {
+            Dispersion::Cauchy {a, b} => a + b / lambda_2,
+            Dispersion::Sellmeier {b, c} => {
+                let sum: f64 = (0..3).map(|i| b[i] * lambda_2 / (lambda_2 - c[i])).sum();
+                
+                (1.0 + sum).max(0.0).sqrt()
+            }
+        }
+    }
+}
+
+// Complex index of refraction of a metal, tabulated every 20nm over the visible range. Gold, silver
+// and copper are from Johnson and Christy (1972), aluminium from Rakić (1995)
+#[derive(Debug, PartialEq)]
+pub struct ComplexIor {
+    eta: [f64; IOR_SAMPLES],
+    k: [f64; IOR_SAMPLES]
+}
+
+const IOR_SAMPLES: usize = 18;
+const IOR_STEP: f64 = 20.0;
+
+pub static GOLD: ComplexIor = ComplexIor {
+    eta: [1.462, 1.468, 1.456, 1.417, 1.349, 1.216, 0.972, 0.636, 0.489, 0.382, 0.299, 0.249, 0.205, 0.172, 0.140, 0.135, 0.131, 0.133],
+    k: [1.929, 1.953, 1.954, 1.932, 1.885, 1.843, 1.873, 2.072, 2.339, 2.594, 2.838, 3.074, 3.304, 3.503, 3.702, 3.882, 4.063, 4.235]
+};
+
+pub static SILVER: ComplexIor = ComplexIor {
+    eta: [0.052, 0.050, 0.046, 0.040, 0.044, 0.050, 0.050, 0.050, 0.057, 0.057, 0.051, 0.055, 0.059, 0.055, 0.050, 0.045, 0.041, 0.037],
+    k: [1.841, 2.104, 2.348, 2.553, 2.751, 2.947, 3.131, 3.315, 3.505, 3.679, 3.841, 4.010, 4.177, 4.332, 4.487, 4.645, 4.803, 4.960]
+};
+
+pub static COPPER: ComplexIor = ComplexIor {
+    eta: [1.206, 1.175, 1.178, 1.171, 1.160, 1.148, 1.134, 1.111, 1.033, 0.861, 0.603, 0.390, 0.272, 0.227, 0.212, 0.211, 0.215, 0.220],
+    k: [2.094, 2.130, 2.248, 2.349, 2.436, 2.515, 2.574, 2.602, 2.582, 2.593, 2.710, 2.943, 3.241, 3.511, 3.752, 3.962, 4.158, 4.345]
+};
+
+pub static ALUMINIUM: ComplexIor = ComplexIor {
+    eta: [0.440, 0.490, 0.542, 0.594, 0.650, 0.710, 0.770, 0.846, 0.922, 1.008, 1.104, 1.200, 1.316, 1.432, 1.558, 1.694, 1.830, 1.977],
+    k: [4.610, 4.860, 5.104, 5.348, 5.592, 5.836, 6.080, 6.324, 6.568, 6.804, 7.032, 7.260, 7.484, 7.708, 7.918, 8.114, 8.310, 8.503]
+};
+
+impl ComplexIor {
+    // Eta and k at a wavelength in nanometers, interpolated linearly between the table entries
+    pub fn at(&self, wavelength: f64) -> (f64, f64) {
+        let x = ((wavelength - WAVELENGTH_MIN) / IOR_STEP).clamp(0.0, (IOR_SAMPLES - 1) as f64);
+        let i = (x as usize).min(IOR_SAMPLES - 2);
+        let t = x - i as f64;
+        
+        (self.eta[i] + t * (self.eta[i + 1] - self.eta[i]), self.k[i] + t * (self.k[i + 1] - self.k[i]))
+    }
+    
+    // Eta and k at the three wavelengths of a path, laid out like its path values
+    pub fn at_wavelengths(&self, wavelengths: Wavelengths) -> (Color01, Color01) {
+        let [hero, second, third] = wavelengths.lambda.map(|lambda| self.at(lambda));
+        
+        (Color01::new(hero.0, second.0, third.0), Color01::new(hero.1, second.1, third.1))
+    }
+    
+    // Per channel eta and k for RGB renders, taken at wavelengths typical of red, green and blue
+    pub fn rgb(&self) -> (Color01, Color01) {
+        self.at_wavelengths(Wavelengths {lambda: [630.0, 532.0, 465.0]})
+    }
+}
+
+struct Normalization {
+    // Integral of the luminance matching function over the visible range
+    luminance: f64,
+    // Linear sRGB color a constant spectrum converts to before white balancing
+    white: Color01
+}
+
+fn normalization() -> &'static Normalization {
+    static NORMALIZATION: OnceLock<Normalization> = OnceLock::new();
+    
+    NORMALIZATION.get_or_init(|| {
+        let steps = (WAVELENGTH_MAX - WAVELENGTH_MIN) as usize;
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for step in 0..steps {
+            let [x_bar, y_bar, z_bar] = matching_functions(WAVELENGTH_MIN + step as f64 + 0.5);
+            x += x_bar;
+            y += y_bar;
+            z += z_bar;
+        }
+        
+        Normalization {luminance: y, white: Color01::from_xyz(x / y, 1.0, z / y)}
+    })
+}
+
+// CIE 1931 2° color matching functions, using the multi-lobe Gaussian fit by Wyman et al. (2013)
+fn matching_functions(wavelength: f64) -> [f64; 3] {
+    let lobe = |mean: f64, sigma_below: f64, sigma_above: f64| {
+        let t = (wavelength - mean) / if wavelength < mean { sigma_below } else { sigma_above };
+        
+        (-0.5 * t * t).exp()
+    };
+    
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7) - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8)
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::microfacet::fresnel_conductor;
+    
+    // Color of the normal incidence reflectance, averaged over hero wavelengths spread evenly over the
+    // visible range
+    fn spectral_reflectance(ior: &ComplexIor) -> Color01 {
+        let steps = 340;
+        let mut sum = Color01::default();
+        for i in 0..steps {
+            let wavelengths = Wavelengths::from_hero(WAVELENGTH_MIN + (i as f64 + 0.5) * (WAVELENGTH_MAX - WAVELENGTH_MIN) / steps as f64);
+            let (eta, k) = ior.at_wavelengths(wavelengths);
+            sum += wavelengths.to_rgb(fresnel_conductor(1.0, &eta, &k));
+        }
+        
+        sum * (1.0 / steps as f64)
+    }
+    
+    #[test]
+    fn measured_metals_match_their_rgb_stand_in() {
+        for ior in [&GOLD, &SILVER, &COPPER, &ALUMINIUM] {
+            let spectral = spectral_reflectance(ior);
+            let (eta, k) = ior.rgb();
+            let rgb = fresnel_conductor(1.0, &eta, &k);
+            
+            for (a, b) in [(spectral.r, rgb.r), (spectral.g, rgb.g), (spectral.b, rgb.b)] {
+                assert!((a - b).abs() < 0.08, "spectral {:?}, rgb {:?}", spectral, rgb);
+            }
+        }
+        
+        // Gold and copper absorb blue, silver and aluminium stay close to white
+        let gold = spectral_reflectance(&GOLD);
+        assert!(gold.r > gold.g && gold.g > gold.b && gold.b < 0.5, "{:?}", gold);
+        let silver = spectral_reflectance(&SILVER);
+        assert!(silver.r - silver.b < 0.05 && silver.b > 0.9, "{:?}", silver);
+    }
+    
+    #[test]
+    fn complex_ior_interpolates_between_entries() {
+        assert_eq!(GOLD.at(WAVELENGTH_MIN), (1.462, 1.929));
+        assert_eq!(GOLD.at(WAVELENGTH_MAX), (0.133, 4.235));
+        
+        let (eta, k) = COPPER.at(590.0);
+        assert!((eta - (0.603 + 0.390) / 2.0).abs() < 1e-12 && (k - (2.710 + 2.943) / 2.0).abs() < 1e-12);
+    }
+}
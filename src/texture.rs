@@ -0,0 +1,165 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::color::Color01;
+use crate::image::Image;
+use crate::perlin::Perlin;
+use crate::vector::Vec3;
+
+pub trait Texture: fmt::Debug + Send + Sync {
+    fn value(&self, u: f64, v: f64, point: &Vec3) -> Color01;
+}
+
+// A plain color is the solid color texture
+impl Texture for Color01 {
+    fn value(&self, _u: f64, _v: f64, _point: &Vec3) -> Color01 {
+        *self
+    }
+}
+
+// Uniform gray texture, used for scalar material parameters that aren't mapped
+pub fn constant(value: f64) -> Arc<dyn Texture> {
+    Arc::new(Color01::new(value, value, value))
+}
+
+// Alternates between two textures on a 3D grid of cubes with the given edge length
+#[derive(Clone, Debug)]
+pub struct CheckerTexture {
+    pub scale: f64,
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, point: &Vec3) -> Color01 {
+        let inv_scale = 1.0 / self.scale;
+        let x = (point.x * inv_scale).floor() as i64;
+        let y = (point.y * inv_scale).floor() as i64;
+        let z = (point.z * inv_scale).floor() as i64;
+        
+        if (x + y + z) % 2 == 0 {
+            self.even.value(u, v, point)
+        }
+        else {
+            self.odd.value(u, v, point)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp
+}
+
+impl WrapMode {
+    fn apply(&self, coord: i64, size: u32) -> usize {
+        let size = size as i64;
+        
+        let wrapped = match self {
+            WrapMode::Repeat => coord.rem_euclid(size),
+            WrapMode::Mirror => {
+                let period = coord.rem_euclid(2 * size);
+                if period < size { period } else { 2 * size - 1 - period }
+            },
+            WrapMode::Clamp => coord.clamp(0, size - 1)
+        };
+        
+        wrapped as usize
+    }
+}
+
+pub struct ImageTexture {
+    pub image: Image,
+    pub wrap: WrapMode
+}
+
+impl ImageTexture {
+    pub fn load(path: &Path, wrap: WrapMode) -> io::Result<ImageTexture> {
+        Ok(ImageTexture {
+            image: Image::load_png(path)?,
+            wrap
+        })
+    }
+    
+    fn texel(&self, x: i64, y: i64) -> Color01 {
+        let x = self.wrap.apply(x, self.image.width);
+        let y = self.wrap.apply(y, self.image.height);
+        
+        self.image.pixels[y * self.image.width as usize + x]
+    }
+}
+
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImageTexture")
+            .field("width", &self.image.width)
+            .field("height", &self.image.height)
+            .field("wrap", &self.wrap)
+            .finish()
+    }
+}
+
+impl Texture for ImageTexture {
+    // Bilinear filtering between the four texels around the sample, v points up in the image
+    fn value(&self, u: f64, v: f64, _point: &Vec3) -> Color01 {
+        if self.image.width == 0 || self.image.height == 0 {
+            return Color01::new(0.0, 1.0, 1.0);
+        }
+        
+        let x = u * self.image.width as f64 - 0.5;
+        let y = (1.0 - v) * self.image.height as f64 - 0.5;
+        
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        
+        let top = (1.0 - tx) * self.texel(x0, y0) + tx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - tx) * self.texel(x0, y0 + 1) + tx * self.texel(x0 + 1, y0 + 1);
+        
+        (1.0 - ty) * top + ty * bottom
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    Smooth,
+    Turbulence,
+    Marble
+}
+
+#[derive(Clone, Debug)]
+pub struct NoiseTexture {
+    pub perlin: Perlin,
+    pub scale: f64,
+    pub kind: NoiseKind,
+    pub color: Color01
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64, kind: NoiseKind, color: Color01) -> NoiseTexture {
+        NoiseTexture {
+            perlin: Perlin::new(),
+            scale,
+            kind,
+            color
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, point: &Vec3) -> Color01 {
+        let intensity = match self.kind {
+            NoiseKind::Smooth => 0.5 * (1.0 + self.perlin.noise(&(*point * self.scale))),
+            NoiseKind::Turbulence => self.perlin.turbulence(&(*point * self.scale), 7),
+            NoiseKind::Marble => 0.5 * (1.0 + (self.scale * point.z + 10.0 * self.perlin.turbulence(point, 7)).sin())
+        };
+        
+        self.color * intensity
+    }
+}
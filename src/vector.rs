@@ -1,6 +1,6 @@
-use std::ops::{ Add, Mul, Range, Sub, Neg };
+use std::ops::{ Add, Index, Mul, Range, Sub, Neg };
 
-use rand::{random, Rng};
+use crate::rt_util::{random, random_range};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vec3 {
@@ -11,7 +11,7 @@ pub struct Vec3 {
 
 impl Vec3 {
     pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
-        Vec3 {x: x, y: y, z: z}
+        Vec3 {x, y, z}
     }
     
     pub fn len_sqr(&self) -> f64 {
@@ -31,7 +31,7 @@ impl Vec3 {
     }
     
     pub fn reflect(&self, normal: &Vec3) -> Vec3 {
-        let proj = *normal * dot(&self, normal);
+        let proj = *normal * dot(self, normal);
         
         *self - (2.0 * proj)
     }
@@ -50,14 +50,20 @@ impl Vec3 {
         Vec3 {x: self.x * inv_len, y: self.y * inv_len, z: self.z * inv_len}
     }
     
+    pub fn min(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+    
+    pub fn max(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+    
     pub fn random() -> Vec3 {
         Vec3::new(random(), random(), random())
     }
     
     pub fn random_range(range: Range<f64>) -> Vec3 {
-        let mut rng = rand::thread_rng();
-        
-        Vec3::new(rng.gen_range(range.clone()), rng.gen_range(range.clone()), rng.gen_range(range.clone()))
+        Vec3::new(random_range(range.clone()), random_range(range.clone()), random_range(range))
     }
 }
 
@@ -145,6 +151,18 @@ impl Mul<Vec3> for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+    
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            _ => &self.z
+        }
+    }
+}
+
 pub fn dot(a: &Vec3, b: &Vec3) -> f64 {
     a.x * b.x + a.y * b.y + a.z * b.z
 }
@@ -156,3 +174,34 @@ pub fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
         z: a.x * b.y - b.x * a.y
     }
 }
+
+// Orthonormal basis around a direction, w is the direction itself
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3
+}
+
+impl Onb {
+    pub fn new(direction: &Vec3) -> Onb {
+        let w = direction.normalized();
+        
+        // Branchless basis construction (Duff et al. 2017)
+        let sign = 1f64.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        let u = Vec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x);
+        let v = Vec3::new(b, sign + w.y * w.y * a, -w.y);
+        
+        Onb {u, v, w}
+    }
+    
+    pub fn local_to_world(&self, local: &Vec3) -> Vec3 {
+        local.x * self.u + local.y * self.v + local.z * self.w
+    }
+    
+    pub fn world_to_local(&self, world: &Vec3) -> Vec3 {
+        Vec3::new(dot(world, &self.u), dot(world, &self.v), dot(world, &self.w))
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use crate::perlin::Perlin;
+use crate::vector::*;
+
+// Dense grid of scalar values stretched over the unit cube, stored with x varying fastest
+pub struct VoxelGrid {
+    size: [usize; 3],
+    values: Vec<f32>,
+    max_value: f64
+}
+
+impl VoxelGrid {
+    pub fn new(size: [usize; 3], values: Vec<f32>) -> VoxelGrid {
+        let max_value = values.iter().fold(0.0f32, |max, value| max.max(*value)) as f64;
+        
+        VoxelGrid {size, values, max_value}
+    }
+    
+    // Mitsuba's .vol format: "VOL" and version 3, then little endian int32 encoding, x, y and z
+    // resolution and channel count, six float32 bounds and the data. Only float32 and uint8 encodings
+    // are read, multiple channels are averaged and the bounds are left to the scene
+    pub fn load_vol(path: &Path) -> io::Result<VoxelGrid> {
+        let mut bytes = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+        
+        VoxelGrid::parse_vol(&bytes)
+    }
+    
+    fn parse_vol(bytes: &[u8]) -> io::Result<VoxelGrid> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        if bytes.len() < 48 || &bytes[0..3] != b"VOL" || bytes[3] != 3 {
+            return Err(invalid("not a version 3 .vol file"));
+        }
+        
+        let int = |offset: usize| i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let encoding = int(4);
+        let dims = [int(8), int(12), int(16)];
+        let channels = int(20);
+        if dims.iter().any(|dim| *dim <= 0) || channels <= 0 {
+            return Err(invalid("grid resolution and channel count must be positive"));
+        }
+        
+        let size = dims.map(|dim| dim as usize);
+        let channels = channels as usize;
+        // A corrupt header can ask for more values than a usize holds
+        let count = size[0].checked_mul(size[1])
+            .and_then(|count| count.checked_mul(size[2]))
+            .and_then(|count| count.checked_mul(channels))
+            .ok_or_else(|| invalid("grid resolution and channel count are too large"))?;
+        let data = &bytes[48..];
+        
+        let raw: Vec<f32> = match encoding {
+            1 if data.len() / 4 >= count => data.chunks_exact(4).take(count).map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap())).collect(),
+            3 if data.len() >= count => data[..count].iter().map(|byte| *byte as f32 / 255.0).collect(),
+            1 | 3 => return Err(invalid("file ends before the grid data does")),
+            _ => return Err(invalid("only float32 and uint8 grids are supported"))
+        };
+        
+        if raw.iter().any(|value| !value.is_finite() || *value < 0.0) {
+            return Err(invalid("grid values must be finite and not negative"));
+        }
+        
+        let values = raw.chunks_exact(channels).map(|voxel| voxel.iter().sum::<f32>() / channels as f32).collect();
+        
+        Ok(VoxelGrid::new(size, values))
+    }
+    
+    // Round puff of Perlin turbulence thinning out towards the sides of the cube, so a cloud can be
+    // made without a file. Values are between 0 and 1
+    pub fn noise_cloud(resolution: usize, frequency: f64) -> VoxelGrid {
+        let perlin = Perlin::new();
+        let resolution = resolution.max(1);
+        let mut values = Vec::with_capacity(resolution * resolution * resolution);
+        
+        for z in 0..resolution {
+            for y in 0..resolution {
+                for x in 0..resolution {
+                    let point = Vec3::new(x as f64 + 0.5, y as f64 + 0.5, z as f64 + 0.5) * (1.0 / resolution as f64);
+                    // 0 at the center and 1 at the middle of each face
+                    let radius = (point - Vec3::new(0.5, 0.5, 0.5)).len() * 2.0;
+                    let detail = perlin.turbulence(&(point * frequency), 5);
+                    
+                    values.push((2.0 * (1.0 - radius) + detail - 0.8).clamp(0.0, 1.0) as f32);
+                }
+            }
+        }
+        
+        VoxelGrid::new([resolution; 3], values)
+    }
+    
+    // Same resolution with `function` applied to every voxel
+    pub fn map(&self, function: impl Fn(f64) -> f64) -> VoxelGrid {
+        VoxelGrid::new(self.size, self.values.iter().map(|value| function(*value as f64) as f32).collect())
+    }
+    
+    pub fn max_value(&self) -> f64 {
+        self.max_value
+    }
+    
+    // Trilinear interpolation between voxel centers, `local` is clamped to the unit cube
+    pub fn sample(&self, local: &Vec3) -> f64 {
+        let mut base = [0; 3];
+        let mut next = [0; 3];
+        let mut fraction = [0.0; 3];
+        
+        for axis in 0..3 {
+            let coord = (local[axis].clamp(0.0, 1.0) * self.size[axis] as f64 - 0.5).max(0.0);
+            let cell = (coord.floor() as usize).min(self.size[axis] - 1);
+            
+            base[axis] = cell;
+            next[axis] = (cell + 1).min(self.size[axis] - 1);
+            fraction[axis] = coord - cell as f64;
+        }
+        
+        let mut result = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for axis in 0..3 {
+                if corner & (1 << axis) == 0 {
+                    index[axis] = base[axis];
+                    weight *= 1.0 - fraction[axis];
+                }
+                else {
+                    index[axis] = next[axis];
+                    weight *= fraction[axis];
+                }
+            }
+            
+            result += weight * self.voxel(index);
+        }
+        
+        result
+    }
+    
+    fn voxel(&self, index: [usize; 3]) -> f64 {
+        self.values[(index[2] * self.size[1] + index[1]) * self.size[0] + index[0]] as f64
+    }
+}
+
+impl fmt::Debug for VoxelGrid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VoxelGrid")
+            .field("size", &self.size)
+            .field("max_value", &self.max_value)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn vol_bytes(encoding: i32, dims: [i32; 3], channels: i32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"VOL\x03".to_vec();
+        for value in [encoding, dims[0], dims[1], dims[2], channels] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        // Bounds, unused
+        bytes.extend_from_slice(&[0; 24]);
+        bytes.extend_from_slice(data);
+        
+        bytes
+    }
+    
+    fn floats(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+    
+    #[test]
+    fn reads_float_grids_and_averages_channels() {
+        let grid = VoxelGrid::parse_vol(&vol_bytes(1, [2, 1, 1], 2, &floats(&[1.0, 3.0, 0.0, 0.5]))).unwrap();
+        
+        assert_eq!(grid.size, [2, 1, 1]);
+        assert_eq!(grid.values, vec![2.0, 0.25]);
+    }
+    
+    #[test]
+    fn rejects_oversized_headers() {
+        let error = VoxelGrid::parse_vol(&vol_bytes(1, [i32::MAX, i32::MAX, i32::MAX], i32::MAX, &[])).unwrap_err();
+        assert!(error.to_string().contains("too large"), "{}", error);
+        
+        let error = VoxelGrid::parse_vol(&vol_bytes(1, [4, 4, 4], 1, &floats(&[1.0; 8]))).unwrap_err();
+        assert!(error.to_string().contains("file ends"), "{}", error);
+    }
+    
+    #[test]
+    fn rejects_negative_and_non_finite_values() {
+        for value in [-0.5, f32::NAN, f32::INFINITY] {
+            let error = VoxelGrid::parse_vol(&vol_bytes(1, [2, 1, 1], 1, &floats(&[1.0, value]))).unwrap_err();
+            assert!(error.to_string().contains("not negative"), "{}", error);
+        }
+    }
+}